@@ -0,0 +1,29 @@
+-- Add migration script here
+CREATE TABLE lists(
+	id uuid PRIMARY KEY,
+	slug TEXT NOT NULL UNIQUE,
+	name TEXT NOT NULL,
+	created_at timestamptz NOT NULL
+);
+
+CREATE TABLE list_subscriptions(
+	list_id uuid NOT NULL
+		REFERENCES lists (id),
+	subscriber_id uuid NOT NULL
+		REFERENCES subscriptions (id),
+	status TEXT NOT NULL,
+	-- Token of the signup that requested this list, confirming it confirms the list
+	subscription_token TEXT NULL
+		REFERENCES subscription_tokens (subscription_token),
+	subscribed_at timestamptz NOT NULL,
+	PRIMARY KEY (list_id, subscriber_id)
+);
+
+-- Everyone who signed up before lists existed was subscribed to the single newsletter
+INSERT INTO lists (id, slug, name, created_at)
+VALUES (gen_random_uuid(), 'newsletter', 'Newsletter', now());
+
+INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscribed_at)
+SELECT lists.id, subscriptions.id, subscriptions.status, subscriptions.subscribed_at
+FROM subscriptions, lists
+WHERE lists.slug = 'newsletter';
@@ -0,0 +1,21 @@
+-- Add migration script here
+BEGIN;
+	-- Long lived token linked from every email to reach the preference center
+	ALTER TABLE subscriptions ADD COLUMN preferences_token TEXT NULL UNIQUE;
+	UPDATE subscriptions
+		SET preferences_token = md5(random()::text || id::text)
+		WHERE preferences_token IS NULL;
+	ALTER TABLE subscriptions ALTER COLUMN preferences_token SET NOT NULL;
+
+	ALTER TABLE subscriptions ADD COLUMN email_format TEXT NOT NULL DEFAULT 'html';
+
+	CREATE TABLE preference_changes(
+		id uuid PRIMARY KEY,
+		subscriber_id uuid NOT NULL
+			REFERENCES subscriptions (id),
+		field TEXT NOT NULL,
+		old_value TEXT NOT NULL,
+		new_value TEXT NOT NULL,
+		changed_at timestamptz NOT NULL
+	);
+COMMIT;
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- Tokens are no longer only used to confirm a signup
+ALTER TABLE subscription_tokens
+ADD COLUMN purpose TEXT NOT NULL DEFAULT 'confirm_subscription';
+
+-- Address awaiting verification for 'change_email' tokens
+ALTER TABLE subscription_tokens
+ADD COLUMN new_email TEXT NULL;
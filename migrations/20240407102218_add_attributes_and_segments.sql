@@ -0,0 +1,9 @@
+-- Add migration script here
+ALTER TABLE subscriptions
+ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}';
+
+CREATE TABLE segments(
+	name TEXT PRIMARY KEY,
+	expression TEXT NOT NULL,
+	created_at timestamptz NOT NULL
+);
@@ -0,0 +1,18 @@
+-- Add migration script here
+CREATE TABLE email_outbox(
+	id uuid PRIMARY KEY,
+	recipient TEXT NOT NULL,
+	subject TEXT NOT NULL,
+	html_part TEXT,
+	text_part TEXT NOT NULL,
+	-- Correlate the delivery with the request that queued it
+	request_id TEXT,
+	traceparent TEXT,
+	attempts INT NOT NULL DEFAULT 0,
+	-- NULL once delivery has been given up on
+	next_attempt_at timestamptz,
+	last_error TEXT,
+	created_at timestamptz NOT NULL
+);
+
+CREATE INDEX email_outbox_next_attempt_at_idx ON email_outbox (next_attempt_at);
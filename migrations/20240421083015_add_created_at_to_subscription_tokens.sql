@@ -0,0 +1,4 @@
+-- Confirmation links expire, counting from when their token was issued.
+-- Tokens issued before this column existed count from now.
+ALTER TABLE subscription_tokens
+ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
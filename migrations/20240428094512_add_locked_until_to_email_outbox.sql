@@ -0,0 +1,3 @@
+-- Emails are claimed for the duration of a delivery attempt, instead of
+-- staying row-locked in a transaction held open across the email API call.
+ALTER TABLE email_outbox ADD COLUMN locked_until timestamptz;
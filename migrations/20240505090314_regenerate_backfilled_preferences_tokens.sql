@@ -0,0 +1,6 @@
+-- Preference tokens backfilled for existing subscribers were derived from
+-- random(), which is predictable. They are the only ones shaped like an md5
+-- digest: tokens of newer subscribers are generated by the app.
+UPDATE subscriptions
+	SET preferences_token = gen_random_uuid()::text
+	WHERE preferences_token ~ '^[0-9a-f]{32}$';
@@ -0,0 +1,165 @@
+use actix_web::http::header::HeaderMap;
+use argon2::{
+    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
+    PasswordVerifier, Version,
+};
+use base64::Engine;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    InvalidCredentials(String),
+    UnexpectedError(String),
+}
+
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, String> {
+    let header_value = headers
+        .get("Authorization")
+        .ok_or("The 'Authorization' header was missing")?
+        .to_str()
+        .map_err(|_| "The 'Authorization' header was not a valid UTF8 string")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .ok_or("The authorization scheme was not 'Basic'")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .map_err(|_| "Failed to base64-decode 'Basic' credentials")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .map_err(|_| "The decoded credential string is not valid UTF8")?;
+
+    let (username, password) = decoded_credentials
+        .split_once(':')
+        .ok_or("Credentials must be of the form 'username:password'")?;
+
+    Ok(Credentials {
+        username: username.to_string(),
+        password: Secret::new(password.to_string()),
+    })
+}
+
+#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    pool: &PgPool,
+) -> Result<Uuid, AuthError> {
+    // Verify against a dummy hash when the user does not exist so that both
+    // branches take roughly the same time.
+    let mut user_id = None;
+    let mut expected_password_hash = Secret::new(
+        "$argon2id$v=19$m=15000,t=2,p=1$\
+        gZiV/M1gPc22ElAH/Jh1Hw$\
+        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+            .to_string(),
+    );
+
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, pool)
+            .await
+            .map_err(|e| AuthError::UnexpectedError(e.to_string()))?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || {
+        current_span.in_scope(|| verify_password_hash(expected_password_hash, credentials.password))
+    })
+    .await
+    .map_err(|e| AuthError::UnexpectedError(e.to_string()))??;
+
+    user_id.ok_or_else(|| AuthError::InvalidCredentials(String::from("Unknown username")))
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash: Secret<String>,
+    password_candidate: Secret<String>,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .map_err(|e| AuthError::UnexpectedError(e.to_string()))?;
+
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .map_err(|_| AuthError::InvalidCredentials(String::from("Invalid password")))
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(
+    username: &str,
+    pool: &PgPool,
+) -> Result<Option<(Uuid, Secret<String>)>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT user_id, password_hash FROM users
+        WHERE username = $1"#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?
+    .map(|row| (row.user_id, Secret::new(row.password_hash)));
+
+    Ok(row)
+}
+
+/// Hashes a password the way [`validate_credentials`] expects to find it.
+pub fn compute_password_hash(password: &Secret<String>) -> Result<Secret<String>, String> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).map_err(|e| e.to_string())?,
+    )
+    .hash_password(password.expose_secret().as_bytes(), &salt)
+    .map_err(|e| e.to_string())?
+    .to_string();
+    Ok(Secret::new(password_hash))
+}
+
+#[tracing::instrument(name = "Create user", skip(password, pool))]
+pub async fn create_user(
+    username: &str,
+    password: Secret<String>,
+    pool: &PgPool,
+) -> Result<Uuid, String> {
+    let password_hash = tokio::task::spawn_blocking(move || compute_password_hash(&password))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)"#,
+        user_id,
+        username,
+        password_hash.expose_secret(),
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(e) if e.is_unique_violation() => {
+            format!("A user named {} already exists", username)
+        }
+        e => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e.to_string()
+        }
+    })?;
+
+    Ok(user_id)
+}
@@ -0,0 +1,180 @@
+use std::{
+    io::{BufRead, IsTerminal, Write},
+    process::{Command as Process, Stdio},
+};
+
+use clap::{Parser, Subcommand};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::{
+    authentication::create_user,
+    configuration::{load_config, Settings},
+    domain::SubscriberEmail,
+    startup::{get_migration_pool, pending_migrations, MIGRATOR},
+};
+
+#[derive(Parser)]
+#[command(name = "zero2prod", about = "A newsletter delivery service")]
+pub struct Cli {
+    /// Serves the app when omitted.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Serve the app until SIGTERM or Ctrl-C.
+    Serve,
+    /// Apply the migrations embedded in this build.
+    Migrate {
+        /// List the pending migrations without applying them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Create a user allowed on the admin endpoints, prompting for its
+    /// password.
+    CreateAdmin { username: String },
+    /// Send an email to `address`, to check the email API configuration.
+    SendTestEmail { address: String },
+    /// Inspect the configuration.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Print the effective configuration, with secrets masked, and report
+    /// every problem found in it.
+    Check,
+}
+
+/// Prints the merged configuration for the current `APP_ENV` and validates
+/// it. Returns whether it is valid.
+pub fn check_config() -> bool {
+    let (settings, environment) = match load_config() {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("Failed to load configuration: {}", e);
+            return false;
+        }
+    };
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&settings).expect("Failed to serialize configuration")
+    );
+    match settings.validate(&environment) {
+        Ok(()) => {
+            eprintln!(
+                "Configuration for the {} environment is valid",
+                environment.as_str()
+            );
+            true
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            false
+        }
+    }
+}
+
+pub async fn migrate(config: Settings, dry_run: bool) -> Result<(), String> {
+    let pool = get_migration_pool(&config.database)
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+    let pending = pending_migrations(&pool)
+        .await
+        .map_err(|e| format!("Failed to read applied migrations: {}", e))?;
+
+    if pending.is_empty() {
+        println!("The database is up to date");
+        return Ok(());
+    }
+    for migration in &pending {
+        println!("{} {}", migration.version, migration.description);
+    }
+    if dry_run {
+        println!("{} migration(s) pending", pending.len());
+        return Ok(());
+    }
+
+    MIGRATOR
+        .run(&pool)
+        .await
+        .map_err(|e| format!("Failed to migrate the database: {}", e))?;
+    println!("Applied {} migration(s)", pending.len());
+    Ok(())
+}
+
+pub async fn create_admin(config: Settings, username: String) -> Result<(), String> {
+    let password = prompt_password("Password: ").map_err(|e| e.to_string())?;
+    if password.expose_secret().is_empty() {
+        return Err(String::from("The password cannot be empty"));
+    }
+    if std::io::stdin().is_terminal() {
+        let confirmation = prompt_password("Confirm password: ").map_err(|e| e.to_string())?;
+        if confirmation.expose_secret() != password.expose_secret() {
+            return Err(String::from("The passwords do not match"));
+        }
+    }
+
+    let pool = connect(&config).await?;
+    let user_id = create_user(&username, password, &pool).await?;
+    println!("Created admin {} ({})", username, user_id);
+    Ok(())
+}
+
+pub async fn send_test_email(config: Settings, address: String) -> Result<(), String> {
+    let recipient = SubscriberEmail::parse(address)?;
+    let email_client = config.email_client.client();
+    email_client
+        .send_email(
+            recipient,
+            "Test email",
+            "<p>The email API is correctly configured.</p>",
+            "The email API is correctly configured.",
+        )
+        .await
+        .map_err(|e| format!("Failed to send the test email: {}", e))?;
+    println!("Test email sent");
+    Ok(())
+}
+
+async fn connect(config: &Settings) -> Result<PgPool, String> {
+    PgPool::connect_with(config.database.with_db())
+        .await
+        .map_err(|e| format!("Failed to connect to the database: {}", e))
+}
+
+/// Reads a line from stdin, without echoing it when typed in a terminal.
+fn prompt_password(prompt: &str) -> std::io::Result<Secret<String>> {
+    eprint!("{}", prompt);
+    std::io::stderr().flush()?;
+
+    let is_terminal = std::io::stdin().is_terminal();
+    if is_terminal {
+        set_echo(false);
+    }
+    let mut password = String::new();
+    let read = std::io::stdin().lock().read_line(&mut password);
+    if is_terminal {
+        set_echo(true);
+        eprintln!();
+    }
+    read?;
+
+    Ok(Secret::new(
+        password.trim_end_matches(['\r', '\n']).to_string(),
+    ))
+}
+
+fn set_echo(enabled: bool) {
+    // Best effort: without stty the password is echoed, but still read
+    let _ = Process::new("stty")
+        .arg(if enabled { "echo" } else { "-echo" })
+        .stdin(Stdio::inherit())
+        .status();
+}
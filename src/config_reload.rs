@@ -0,0 +1,275 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    configuration::{
+        config_dir, config_file_candidates, current_environment, load_config_from, Environment,
+        Settings,
+    },
+    email_client::EmailClient,
+    shutdown::Shutdown,
+    telemetry::{Configured, LogFilter},
+};
+
+/// The settings applied to the running app when the configuration files
+/// change. Any other change only takes effect on the next restart.
+const RELOADABLE: [&str; 2] = ["email_client.timeout_ms", "telemetry.log_level"];
+
+/// Checks the configuration files every `interval` until shutdown, applying
+/// the changes to [`RELOADABLE`] settings. Meant to be run with
+/// [`Shutdown::spawn_worker`].
+pub async fn watch_config_until_stopped(
+    config: Settings,
+    email_client: EmailClient,
+    log_filter: LogFilter,
+    interval: Duration,
+    shutdown: Shutdown,
+) {
+    let dir = config_dir();
+    let environment = match current_environment() {
+        Ok(environment) => environment,
+        Err(e) => {
+            tracing::error!("Not watching the configuration files: {}", e);
+            return;
+        }
+    };
+    let mut current = config;
+    let mut seen = fingerprint(&dir, &environment);
+
+    while !shutdown.is_triggered() {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown.requested() => break,
+        }
+        let latest = fingerprint(&dir, &environment);
+        if latest == seen {
+            continue;
+        }
+        seen = latest;
+
+        let reloaded = load_config_from(&dir, &environment)
+            .map_err(|e| e.to_string())
+            .and_then(|config| {
+                config
+                    .validate(&environment)
+                    .map(|()| config)
+                    .map_err(|e| e.to_string())
+            });
+        match reloaded {
+            Ok(reloaded) => {
+                apply_changes(&current, &reloaded, &email_client, &log_filter);
+                current = reloaded;
+            }
+            Err(e) => tracing::error!("Ignoring configuration change: {}", e),
+        }
+    }
+}
+
+type Fingerprint = Vec<(PathBuf, Option<(SystemTime, u64)>)>;
+
+/// When each file the configuration may be read from was last modified, and
+/// its size, to notice edits as well as files being added or removed.
+fn fingerprint(dir: &Path, environment: &Environment) -> Fingerprint {
+    config_file_candidates(dir, environment)
+        .into_iter()
+        .map(|path| {
+            let metadata = std::fs::metadata(&path)
+                .and_then(|metadata| Ok((metadata.modified()?, metadata.len())))
+                .ok();
+            (path, metadata)
+        })
+        .collect()
+}
+
+fn apply_changes(
+    current: &Settings,
+    reloaded: &Settings,
+    email_client: &EmailClient,
+    log_filter: &LogFilter,
+) {
+    if reloaded.email_client.timeout_ms != current.email_client.timeout_ms {
+        email_client.set_timeout(reloaded.email_client.timeout());
+        tracing::info!(
+            "Email API timeout set to {}ms",
+            reloaded.email_client.timeout_ms
+        );
+    }
+
+    if reloaded.telemetry.log_level != current.telemetry.log_level {
+        if std::env::var_os("RUST_LOG").is_some() {
+            tracing::warn!("Not changing the log filter, RUST_LOG takes precedence");
+        } else {
+            let log_level = &reloaded.telemetry.log_level;
+            match log_filter.configure(log_level) {
+                Ok(Configured::Applied) => tracing::info!("Log filter set to {}", log_level),
+                Ok(Configured::AfterOverride) => tracing::info!(
+                    "Log filter set to {} once the admin override expires",
+                    log_level
+                ),
+                Ok(Configured::Overridden) => tracing::warn!(
+                    "Not changing the log filter to {}, it was set through the admin API",
+                    log_level
+                ),
+                Err(e) => tracing::error!("Failed to change log filter: {}", e),
+            }
+        }
+    }
+
+    let restart_required = changed_settings(current, reloaded)
+        .into_iter()
+        .filter(|setting| !RELOADABLE.contains(&setting.as_str()))
+        .collect::<Vec<_>>();
+    if !restart_required.is_empty() {
+        tracing::warn!(
+            "Restart to apply the configuration changes to {}",
+            restart_required.join(", ")
+        );
+    }
+}
+
+/// The dotted names of the settings that differ. Secrets are compared
+/// masked, so changing one is not noticed.
+fn changed_settings(current: &Settings, reloaded: &Settings) -> Vec<String> {
+    let mut changed = Vec::new();
+    diff(
+        "",
+        &serde_json::to_value(current).expect("Failed to serialize configuration"),
+        &serde_json::to_value(reloaded).expect("Failed to serialize configuration"),
+        &mut changed,
+    );
+    changed
+}
+
+fn diff(
+    path: &str,
+    current: &serde_json::Value,
+    reloaded: &serde_json::Value,
+    changed: &mut Vec<String>,
+) {
+    match (current, reloaded) {
+        (serde_json::Value::Object(current), serde_json::Value::Object(reloaded)) => {
+            let mut keys: Vec<_> = current.keys().chain(reloaded.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let null = serde_json::Value::Null;
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                diff(
+                    &path,
+                    current.get(key).unwrap_or(&null),
+                    reloaded.get(key).unwrap_or(&null),
+                    changed,
+                );
+            }
+        }
+        (current, reloaded) if current != reloaded => changed.push(path.to_string()),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tracing_subscriber::{layer::SubscriberExt, reload, EnvFilter, Registry};
+    use uuid::Uuid;
+
+    use super::{apply_changes, changed_settings, fingerprint};
+    use crate::configuration::{Environment, Settings};
+    use crate::telemetry::LogFilter;
+
+    fn settings() -> Settings {
+        config::Config::builder()
+            .add_source(config::File::from_str(
+                include_str!("../config/base.yaml"),
+                config::FileFormat::Yaml,
+            ))
+            .add_source(config::File::from_str(
+                include_str!("../config/local.yaml"),
+                config::FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn unchanged_settings_are_not_reported() {
+        assert!(changed_settings(&settings(), &settings()).is_empty());
+    }
+
+    #[test]
+    fn changed_settings_are_reported_by_dotted_name() {
+        let mut reloaded = settings();
+        reloaded.email_client.timeout_ms += 1;
+        reloaded.database.max_connections += 1;
+        reloaded.telemetry.otlp_endpoint = Some(String::from("http://localhost:4318"));
+
+        assert_eq!(
+            changed_settings(&settings(), &reloaded),
+            [
+                "database.max_connections",
+                "email_client.timeout_ms",
+                "telemetry.otlp_endpoint"
+            ]
+        );
+    }
+
+    #[test]
+    fn fingerprint_changes_when_a_config_file_is_added() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir(&dir).unwrap();
+        let environment = Environment::Named(String::from("staging"));
+
+        let before = fingerprint(&dir, &environment);
+        std::fs::write(dir.join("staging.toml"), "").unwrap();
+        let after = fingerprint(&dir, &environment);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_ne!(before, after);
+    }
+
+    #[tokio::test]
+    async fn a_log_level_change_waits_for_the_admin_override_to_expire() {
+        let (env_filter, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let _subscriber = Registry::default().with(env_filter);
+        let log_filter = LogFilter::new(handle);
+        let email_client = settings().email_client.client();
+        let mut reloaded = settings();
+        reloaded.telemetry.log_level = String::from("warn");
+
+        log_filter
+            .set("debug", Some(Duration::from_millis(100)))
+            .unwrap();
+        apply_changes(&settings(), &reloaded, &email_client, &log_filter);
+        assert_eq!(log_filter.current().unwrap(), "debug");
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(log_filter.current().unwrap(), "warn");
+    }
+
+    #[tokio::test]
+    async fn unrelated_changes_leave_the_admin_override_in_place() {
+        let (env_filter, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let _subscriber = Registry::default().with(env_filter);
+        let log_filter = LogFilter::new(handle);
+        let email_client = settings().email_client.client();
+        let mut reloaded = settings();
+        reloaded.email_client.timeout_ms += 1;
+
+        log_filter.set("debug", None).unwrap();
+        apply_changes(&settings(), &reloaded, &email_client, &log_filter);
+        assert_eq!(log_filter.current().unwrap(), "debug");
+
+        reloaded.telemetry.log_level = String::from("warn");
+        apply_changes(&settings(), &reloaded, &email_client, &log_filter);
+        assert_eq!(log_filter.current().unwrap(), "debug");
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::{metrics::DB_POOL_ACQUIRE_WAIT_SECONDS, shutdown::Shutdown};
+
+/// The primary database, and a read replica to take read-only queries off it
+/// when one is configured.
+#[derive(Clone)]
+pub struct DbPools {
+    writer: PgPool,
+    replica: Option<Replica>,
+}
+
+#[derive(Clone)]
+struct Replica {
+    pool: PgPool,
+    healthy: Arc<AtomicBool>,
+}
+
+impl DbPools {
+    pub fn new(writer: PgPool, replica: Option<PgPool>) -> Self {
+        Self {
+            writer,
+            replica: replica.map(|pool| Replica {
+                pool,
+                healthy: Arc::new(AtomicBool::new(true)),
+            }),
+        }
+    }
+
+    /// For writes, and reads that must see them.
+    pub fn writer(&self) -> &PgPool {
+        &self.writer
+    }
+
+    /// For read-only queries that can tolerate replication lag: the replica
+    /// while its last health check passed, the primary otherwise.
+    pub fn reader(&self) -> &PgPool {
+        match &self.replica {
+            Some(replica) if replica.healthy.load(Ordering::Relaxed) => &replica.pool,
+            _ => &self.writer,
+        }
+    }
+
+    /// Pings the replica, if any, routing reads back to the primary while it
+    /// does not answer within `timeout`.
+    pub async fn check_replica(&self, timeout: Duration) {
+        let replica = match &self.replica {
+            Some(replica) => replica,
+            None => return,
+        };
+
+        let outcome = tokio::time::timeout(timeout, sqlx::query("SELECT 1").execute(&replica.pool))
+            .await
+            .map_err(|_| String::from("Timed out"))
+            .and_then(|outcome| outcome.map_err(|e| e.to_string()));
+        let was_healthy = replica.healthy.swap(outcome.is_ok(), Ordering::Relaxed);
+        match outcome {
+            Err(e) if was_healthy => {
+                tracing::warn!(error.message = %e, "Read replica is unhealthy, reading from the primary")
+            }
+            Ok(_) if !was_healthy => tracing::info!("Read replica recovered"),
+            _ => {}
+        }
+    }
+
+    pub async fn close(&self) {
+        self.writer.close().await;
+        if let Some(replica) = &self.replica {
+            replica.pool.close().await;
+        }
+    }
+
+    /// Checks the replica every `interval` until shutdown.
+    pub async fn monitor_replica(self, interval: Duration, timeout: Duration, shutdown: Shutdown) {
+        while !shutdown.is_triggered() {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => self.check_replica(timeout).await,
+                _ = shutdown.requested() => {}
+            }
+        }
+    }
+}
+
+/// Opens a transaction on `pool`, recording how long it waited for a
+/// connection.
+pub async fn begin_transaction(
+    pool: &PgPool,
+) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let start = Instant::now();
+    let transaction = pool.begin().await;
+    DB_POOL_ACQUIRE_WAIT_SECONDS.observe(start.elapsed().as_secs_f64());
+    transaction
+}
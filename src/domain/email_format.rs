@@ -0,0 +1,53 @@
+/// How a subscriber prefers to receive our emails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmailFormat {
+    Html,
+    Text,
+}
+
+impl EmailFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Html => "html",
+            Self::Text => "text",
+        }
+    }
+}
+
+impl TryFrom<String> for EmailFormat {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "html" => Ok(Self::Html),
+            "text" => Ok(Self::Text),
+            _ => Err(format!(
+                "Expected either html / text. {value} is not a valid email format"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok_eq};
+
+    use crate::domain::EmailFormat;
+
+    #[test]
+    fn known_formats_are_parsed() {
+        assert_ok_eq!(
+            EmailFormat::try_from(String::from("html")),
+            EmailFormat::Html
+        );
+        assert_ok_eq!(
+            EmailFormat::try_from(String::from("Text")),
+            EmailFormat::Text
+        );
+    }
+
+    #[test]
+    fn unknown_formats_are_rejected() {
+        assert_err!(EmailFormat::try_from(String::from("markdown")));
+    }
+}
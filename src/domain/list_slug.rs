@@ -0,0 +1,108 @@
+/// The list every signup joins when it doesn't ask for a specific one.
+pub const DEFAULT_LIST: &str = "newsletter";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListSlug(String);
+
+impl ListSlug {
+    pub fn parse(s: String) -> Result<Self, String> {
+        let is_empty = s.is_empty();
+
+        let is_too_long = s.len() > 64;
+
+        let has_invalid_chars = !s
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+
+        if is_empty || is_too_long || has_invalid_chars {
+            Err(format!("{s} is not a valid list identifier"))
+        } else {
+            Ok(Self(s))
+        }
+    }
+
+    /// Parses a comma separated list of slugs, falling back to the default list.
+    pub fn parse_many(s: Option<String>) -> Result<Vec<Self>, String> {
+        Self::parse_all(
+            s.as_deref()
+                .unwrap_or(DEFAULT_LIST)
+                .split(',')
+                .map(|slug| slug.trim().to_string())
+                .collect(),
+        )
+    }
+
+    /// Parses and deduplicates `slugs`, at least one being required.
+    pub fn parse_all(slugs: Vec<String>) -> Result<Vec<Self>, String> {
+        if slugs.is_empty() {
+            return Err(String::from("At least one list is required"));
+        }
+        let mut parsed = Vec::new();
+        for slug in slugs {
+            let slug = Self::parse(slug)?;
+            if !parsed.contains(&slug) {
+                parsed.push(slug);
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+impl AsRef<str> for ListSlug {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use crate::domain::ListSlug;
+
+    #[test]
+    fn valid_slug() {
+        assert_ok!(ListSlug::parse(String::from("rust-weekly-2")));
+    }
+
+    #[test]
+    fn empty_slug_is_rejected() {
+        assert_err!(ListSlug::parse(String::from("")));
+    }
+
+    #[test]
+    fn slugs_with_invalid_chars_are_rejected() {
+        for slug in &["Weekly", "rust weekly", "rust_weekly", "ёжик"] {
+            assert_err!(ListSlug::parse(slug.to_string()));
+        }
+    }
+
+    #[test]
+    fn a_slug_longer_than_64_chars_is_rejected() {
+        assert_err!(ListSlug::parse("a".repeat(65)));
+    }
+
+    #[test]
+    fn missing_lists_fall_back_to_the_default_list() {
+        let slugs = ListSlug::parse_many(None).unwrap();
+        let slugs: Vec<_> = slugs.iter().map(|s| s.as_ref()).collect();
+        assert_eq!(slugs, vec!["newsletter"]);
+    }
+
+    #[test]
+    fn many_slugs_are_parsed_and_deduplicated() {
+        let slugs = ListSlug::parse_many(Some(String::from("daily, weekly,daily"))).unwrap();
+        let slugs: Vec<_> = slugs.iter().map(|s| s.as_ref()).collect();
+        assert_eq!(slugs, vec!["daily", "weekly"]);
+    }
+
+    #[test]
+    fn one_invalid_slug_rejects_all() {
+        assert_err!(ListSlug::parse_many(Some(String::from("daily,Weekly"))));
+    }
+
+    #[test]
+    fn an_empty_list_of_slugs_is_rejected() {
+        assert_err!(ListSlug::parse_all(vec![]));
+    }
+}
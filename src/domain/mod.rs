@@ -1,7 +1,15 @@
+mod email_format;
+mod list_slug;
 mod new_subscriber;
+mod segment;
+mod subscriber_attributes;
 mod subscriber_email;
 mod subscriber_name;
 
+pub use email_format::EmailFormat;
+pub use list_slug::{ListSlug, DEFAULT_LIST};
 pub use new_subscriber::NewSubscriber;
+pub use segment::Segment;
+pub use subscriber_attributes::SubscriberAttributes;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
@@ -1,6 +1,11 @@
-use super::{subscriber_email::SubscriberEmail, subscriber_name::SubscriberName};
+use super::{
+    list_slug::ListSlug, subscriber_attributes::SubscriberAttributes,
+    subscriber_email::SubscriberEmail, subscriber_name::SubscriberName,
+};
 
 pub struct NewSubscriber {
     pub name: SubscriberName,
     pub email: SubscriberEmail,
+    pub lists: Vec<ListSlug>,
+    pub attributes: SubscriberAttributes,
 }
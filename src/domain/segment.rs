@@ -0,0 +1,406 @@
+use chrono::NaiveDate;
+use sqlx::{Postgres, QueryBuilder};
+
+use super::subscriber_attributes::is_valid_attribute_key;
+
+/// A filter over subscribers, written as a small expression language:
+///
+/// ```text
+/// country = "DE" and (plan != "free" or signed_up_after 2025-01-01)
+/// ```
+///
+/// Attributes are compared as strings; a missing attribute is never equal to
+/// a value. `signed_up_after` is inclusive and `signed_up_before` exclusive,
+/// both at midnight UTC.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Segment {
+    And(Box<Segment>, Box<Segment>),
+    Or(Box<Segment>, Box<Segment>),
+    Not(Box<Segment>),
+    Equals { attribute: String, value: String },
+    NotEquals { attribute: String, value: String },
+    SignedUpAfter(NaiveDate),
+    SignedUpBefore(NaiveDate),
+}
+
+/// Bounds how deep `not` and parentheses nest, and how many conditions an
+/// expression has, which keeps recursing over its tree within the stack.
+const MAX_NESTING: usize = 32;
+const MAX_CONDITIONS: usize = 100;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Eq,
+    NotEq,
+    LParen,
+    RParen,
+}
+
+impl Segment {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            nesting: 0,
+            conditions: 0,
+        };
+        let segment = parser.expression()?;
+        match parser.peek() {
+            None => Ok(segment),
+            Some(token) => Err(format!("Unexpected {token:?} in segment expression")),
+        }
+    }
+
+    /// Appends this segment as a parenthesised SQL condition on the
+    /// `subscriptions` table, binding every literal as a parameter.
+    pub fn push_sql<'a>(&'a self, builder: &mut QueryBuilder<'a, Postgres>) {
+        builder.push("(");
+        match self {
+            Self::And(left, right) => {
+                left.push_sql(builder);
+                builder.push(" AND ");
+                right.push_sql(builder);
+            }
+            Self::Or(left, right) => {
+                left.push_sql(builder);
+                builder.push(" OR ");
+                right.push_sql(builder);
+            }
+            Self::Not(inner) => {
+                builder.push("NOT ");
+                inner.push_sql(builder);
+            }
+            Self::Equals { attribute, value } => {
+                builder
+                    .push("subscriptions.attributes ->> ")
+                    .push_bind(attribute.as_str())
+                    .push(" = ")
+                    .push_bind(value.as_str());
+            }
+            Self::NotEquals { attribute, value } => {
+                builder
+                    .push("subscriptions.attributes ->> ")
+                    .push_bind(attribute.as_str())
+                    .push(" IS DISTINCT FROM ")
+                    .push_bind(value.as_str());
+            }
+            Self::SignedUpAfter(date) => {
+                builder
+                    .push("subscriptions.subscribed_at >= ")
+                    .push_bind(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+            }
+            Self::SignedUpBefore(date) => {
+                builder
+                    .push("subscriptions.subscribed_at < ")
+                    .push_bind(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+            }
+        }
+        builder.push(")");
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            '=' => {
+                chars.next();
+                tokens.push(Token::Eq);
+            }
+            '!' => {
+                chars.next();
+                if chars.next() != Some('=') {
+                    return Err(String::from("Expected '=' after '!'"));
+                }
+                tokens.push(Token::NotEq);
+            }
+            '"' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => value.push(escaped),
+                            None => return Err(String::from("Unterminated string")),
+                        },
+                        Some(c) => value.push(c),
+                        None => return Err(String::from("Unterminated string")),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            c if c.is_ascii_alphanumeric() || c == '_' || c == '-' => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                        word.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Word(word));
+            }
+            c => return Err(format!("Unexpected character {c:?} in segment expression")),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Factors being parsed, one per enclosing `not` or parenthesis.
+    nesting: usize,
+    conditions: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn next_is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    // expression := term ("or" term)*
+    fn expression(&mut self) -> Result<Segment, String> {
+        let mut segment = self.term()?;
+        while self.next_is_keyword("or") {
+            self.next();
+            segment = Segment::Or(Box::new(segment), Box::new(self.term()?));
+        }
+        Ok(segment)
+    }
+
+    // term := factor ("and" factor)*
+    fn term(&mut self) -> Result<Segment, String> {
+        let mut segment = self.factor()?;
+        while self.next_is_keyword("and") {
+            self.next();
+            segment = Segment::And(Box::new(segment), Box::new(self.factor()?));
+        }
+        Ok(segment)
+    }
+
+    fn factor(&mut self) -> Result<Segment, String> {
+        if self.nesting == MAX_NESTING {
+            return Err(format!(
+                "Segment expressions can nest at most {MAX_NESTING} levels deep"
+            ));
+        }
+        self.nesting += 1;
+        let segment = self.nested_factor();
+        self.nesting -= 1;
+        segment
+    }
+
+    // factor := "not" factor | "(" expression ")" | condition
+    fn nested_factor(&mut self) -> Result<Segment, String> {
+        if self.next_is_keyword("not") {
+            self.next();
+            return Ok(Segment::Not(Box::new(self.factor()?)));
+        }
+
+        match self.next() {
+            Some(Token::LParen) => {
+                let segment = self.expression()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(segment),
+                    _ => Err(String::from("Expected ')' in segment expression")),
+                }
+            }
+            Some(Token::Word(word)) => self.condition(word),
+            Some(token) => Err(format!("Unexpected {token:?} in segment expression")),
+            None => Err(String::from("Unexpected end of segment expression")),
+        }
+    }
+
+    // condition := ("signed_up_after" | "signed_up_before") DATE
+    //            | ATTRIBUTE ("=" | "!=") STRING
+    fn condition(&mut self, word: String) -> Result<Segment, String> {
+        self.conditions += 1;
+        if self.conditions > MAX_CONDITIONS {
+            return Err(format!(
+                "Segment expressions can have at most {MAX_CONDITIONS} conditions"
+            ));
+        }
+        match word.as_str() {
+            "signed_up_after" => Ok(Segment::SignedUpAfter(self.date()?)),
+            "signed_up_before" => Ok(Segment::SignedUpBefore(self.date()?)),
+            attribute => {
+                if !is_valid_attribute_key(attribute) {
+                    return Err(format!("{attribute} is not a valid attribute name"));
+                }
+                let operator = self.next();
+                let value = match self.next() {
+                    Some(Token::Str(value)) => value,
+                    _ => return Err(format!("Expected a quoted value to compare {attribute} to")),
+                };
+                let attribute = attribute.to_string();
+                match operator {
+                    Some(Token::Eq) => Ok(Segment::Equals { attribute, value }),
+                    Some(Token::NotEq) => Ok(Segment::NotEquals { attribute, value }),
+                    _ => Err(format!("Expected '=' or '!=' after {attribute}")),
+                }
+            }
+        }
+    }
+
+    fn date(&mut self) -> Result<NaiveDate, String> {
+        match self.next() {
+            Some(Token::Word(word)) => NaiveDate::parse_from_str(&word, "%Y-%m-%d")
+                .map_err(|_| format!("{word} is not a valid YYYY-MM-DD date")),
+            _ => Err(String::from("Expected a YYYY-MM-DD date")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use claims::{assert_err, assert_ok_eq};
+    use sqlx::{Execute, Postgres, QueryBuilder};
+
+    use crate::domain::Segment;
+
+    fn equals(attribute: &str, value: &str) -> Segment {
+        Segment::Equals {
+            attribute: attribute.into(),
+            value: value.into(),
+        }
+    }
+
+    #[test]
+    fn a_single_comparison_is_parsed() {
+        assert_ok_eq!(Segment::parse(r#"country = "DE""#), equals("country", "DE"));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_ok_eq!(
+            Segment::parse(r#"a = "1" or b = "2" and c = "3""#),
+            Segment::Or(
+                Box::new(equals("a", "1")),
+                Box::new(Segment::And(
+                    Box::new(equals("b", "2")),
+                    Box::new(equals("c", "3"))
+                ))
+            )
+        );
+    }
+
+    #[test]
+    fn parentheses_and_not_are_supported() {
+        assert_ok_eq!(
+            Segment::parse(r#"NOT (a = "1" OR b != "2")"#),
+            Segment::Not(Box::new(Segment::Or(
+                Box::new(equals("a", "1")),
+                Box::new(Segment::NotEquals {
+                    attribute: "b".into(),
+                    value: "2".into()
+                })
+            )))
+        );
+    }
+
+    #[test]
+    fn signup_dates_are_parsed() {
+        assert_ok_eq!(
+            Segment::parse(r#"country = "DE" and signed_up_after 2025-01-01"#),
+            Segment::And(
+                Box::new(equals("country", "DE")),
+                Box::new(Segment::SignedUpAfter(
+                    NaiveDate::from_ymd_opt(2025, 1, 1).unwrap()
+                ))
+            )
+        );
+    }
+
+    #[test]
+    fn quotes_can_be_escaped_in_values() {
+        assert_ok_eq!(
+            Segment::parse(r#"nickname = "the \"boss\"""#),
+            equals("nickname", r#"the "boss""#)
+        );
+    }
+
+    #[test]
+    fn invalid_expressions_are_rejected() {
+        for expression in &[
+            "",
+            "country",
+            r#"country = DE"#,
+            r#"country == "DE""#,
+            r#"Country = "DE""#,
+            r#"country = "DE" and"#,
+            r#"(country = "DE""#,
+            r#"country = "DE")"#,
+            r#"country = "DE"#,
+            "signed_up_after yesterday",
+            "signed_up_before 2025-13-01",
+            r#"country = "DE"; DROP TABLE subscriptions"#,
+        ] {
+            assert_err!(Segment::parse(expression), "{} was accepted", expression);
+        }
+    }
+
+    #[test]
+    fn deeply_nested_expressions_are_rejected() {
+        let nested = |depth: usize| {
+            format!(
+                r#"{}country = "DE"{}"#,
+                "not (".repeat(depth),
+                ")".repeat(depth)
+            )
+        };
+        assert!(Segment::parse(&nested(15)).is_ok());
+        assert_err!(Segment::parse(&nested(16)));
+        assert_err!(Segment::parse(&"(".repeat(100_000)));
+        assert_err!(Segment::parse(&"not ".repeat(100_000)));
+    }
+
+    #[test]
+    fn expressions_with_too_many_conditions_are_rejected() {
+        let conditions = |count: usize| vec![r#"a = "1""#; count].join(" and ");
+        assert!(Segment::parse(&conditions(100)).is_ok());
+        assert_err!(Segment::parse(&conditions(101)));
+    }
+
+    #[test]
+    fn values_are_bound_as_parameters() {
+        let segment =
+            Segment::parse(r#"country = "DE' OR 1=1 --" or signed_up_before 2025-01-01"#).unwrap();
+        let mut builder = QueryBuilder::<Postgres>::new("SELECT id FROM subscriptions WHERE ");
+        segment.push_sql(&mut builder);
+
+        assert_eq!(
+            builder.build().sql(),
+            "SELECT id FROM subscriptions WHERE ((subscriptions.attributes ->> $1 = $2) \
+            OR (subscriptions.subscribed_at < $3))"
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Free-form key/value pairs attached to a subscriber, used for segmentation.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize)]
+pub struct SubscriberAttributes(BTreeMap<String, String>);
+
+impl SubscriberAttributes {
+    pub fn parse(attributes: HashMap<String, String>) -> Result<Self, String> {
+        if attributes.len() > 50 {
+            return Err(String::from("A subscriber can have at most 50 attributes"));
+        }
+
+        let mut parsed = BTreeMap::new();
+        for (key, value) in attributes {
+            if !is_valid_attribute_key(&key) {
+                return Err(format!("{key} is not a valid attribute name"));
+            }
+            if value.chars().count() > 256 {
+                return Err(format!("The value of attribute {key} is too long"));
+            }
+            parsed.insert(key, value);
+        }
+        Ok(Self(parsed))
+    }
+}
+
+impl AsRef<BTreeMap<String, String>> for SubscriberAttributes {
+    fn as_ref(&self) -> &BTreeMap<String, String> {
+        &self.0
+    }
+}
+
+pub fn is_valid_attribute_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    let starts_with_letter = chars.next().is_some_and(|c| c.is_ascii_lowercase());
+    starts_with_letter
+        && key.len() <= 64
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use claims::{assert_err, assert_ok};
+
+    use crate::domain::SubscriberAttributes;
+
+    fn attributes(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn valid_attributes() {
+        assert_ok!(SubscriberAttributes::parse(attributes(&[
+            ("country", "DE"),
+            ("plan_tier2", "pro"),
+        ])));
+    }
+
+    #[test]
+    fn no_attributes_is_valid() {
+        assert_ok!(SubscriberAttributes::parse(HashMap::new()));
+    }
+
+    #[test]
+    fn attribute_keys_with_invalid_chars_are_rejected() {
+        for key in &["", "Country", "2fa", "country code", "country-code", "ёж"] {
+            assert_err!(SubscriberAttributes::parse(attributes(&[(key, "DE")])));
+        }
+    }
+
+    #[test]
+    fn attribute_values_longer_than_256_chars_are_rejected() {
+        let value = "a".repeat(257);
+        assert_err!(SubscriberAttributes::parse(attributes(&[(
+            "country", &value
+        )])));
+    }
+
+    #[test]
+    fn more_than_50_attributes_are_rejected() {
+        let attributes = (0..51)
+            .map(|i| (format!("key{i}"), String::from("value")))
+            .collect();
+        assert_err!(SubscriberAttributes::parse(attributes));
+    }
+}
@@ -13,7 +13,6 @@ impl SubscriberName {
         let contains_forbidden_chars = s.chars().any(|c| forbidden_chars.contains(&c));
 
         if is_empty_or_whitespace || is_too_long || contains_forbidden_chars {
-            dbg!("Recv name: {}", s.clone());
             Err(format!("{s} is not a valid subscriber name"))
         } else {
             Ok(Self(s))
@@ -1,9 +1,20 @@
-use std::time::Duration;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use reqwest::Client;
 use secrecy::{ExposeSecret, Secret};
 
-use crate::domain::SubscriberEmail;
+use crate::{
+    domain::SubscriberEmail,
+    metrics::record_email_sent,
+    request_id::{RequestId, REQUEST_ID_HEADER},
+    telemetry::trace_context_headers,
+};
 
 #[derive(Clone)]
 pub struct EmailClient {
@@ -11,6 +22,9 @@ pub struct EmailClient {
     base_url: String,
     sender: SubscriberEmail,
     authorization_token: Secret<String>,
+    /// Per request timeout in milliseconds, shared by every clone so that it
+    /// can be changed while the app runs.
+    timeout_ms: Arc<AtomicU64>,
 }
 
 impl EmailClient {
@@ -21,13 +35,24 @@ impl EmailClient {
         timeout: Duration,
     ) -> Self {
         Self {
-            http_client: Client::builder().timeout(timeout).build().unwrap(),
+            http_client: Client::new(),
             base_url,
             sender,
             authorization_token,
+            timeout_ms: Arc::new(AtomicU64::new(timeout.as_millis() as u64)),
         }
     }
 
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms.load(Ordering::Relaxed))
+    }
+
+    /// Applies to the requests sent from now on, by this client and its clones.
+    pub fn set_timeout(&self, timeout: Duration) {
+        self.timeout_ms
+            .store(timeout.as_millis() as u64, Ordering::Relaxed);
+    }
+
     pub async fn send_email(
         &self,
         recipient: SubscriberEmail,
@@ -35,6 +60,38 @@ impl EmailClient {
         html_part: &str,
         text_part: &str,
     ) -> Result<(), reqwest::Error> {
+        self.send(recipient, subject, Some(html_part), text_part)
+            .await
+    }
+
+    /// Sends an email without an HTML part, for subscribers who opted for plain text.
+    pub async fn send_text_email(
+        &self,
+        recipient: SubscriberEmail,
+        subject: &str,
+        text_part: &str,
+    ) -> Result<(), reqwest::Error> {
+        self.send(recipient, subject, None, text_part).await
+    }
+
+    /// Checks that the email API is reachable, whatever status it answers with.
+    pub async fn probe(&self) -> Result<(), reqwest::Error> {
+        self.http_client
+            .head(&self.base_url)
+            .timeout(self.timeout())
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn send(
+        &self,
+        recipient: SubscriberEmail,
+        subject: &str,
+        html_part: Option<&str>,
+        text_part: &str,
+    ) -> Result<(), reqwest::Error> {
+        let request_id = RequestId::current();
         let email = SendEmailRequest {
             from: EmailUser {
                 email: self.sender.as_ref(),
@@ -47,24 +104,35 @@ impl EmailClient {
             subject,
             text_part,
             html_part,
+            custom_id: request_id.as_ref().map(AsRef::as_ref),
         };
 
         let req_body = SendEmailRequestFormat {
             messages: vec![email],
         };
 
-        self.http_client
+        let mut headers = trace_context_headers();
+        if let Some(request_id) = &request_id {
+            headers.insert(REQUEST_ID_HEADER, request_id.as_ref().parse().unwrap());
+        }
+
+        let start = Instant::now();
+        let outcome = self
+            .http_client
             .post(format!("{}/send", &self.base_url))
+            .timeout(self.timeout())
+            .headers(headers)
             .json(&req_body)
             .header(
                 "Authorization",
                 format!("Basic {}", self.authorization_token.expose_secret()),
             )
             .send()
-            .await?
-            .error_for_status()?;
+            .await
+            .and_then(|response| response.error_for_status());
+        record_email_sent(outcome.is_ok(), start.elapsed());
 
-        Ok(())
+        outcome.map(|_| ())
     }
 }
 
@@ -81,8 +149,17 @@ struct SendEmailRequest<'a> {
     to: Vec<EmailUser<'a>>,
     subject: &'a str,
     text_part: &'a str,
-    #[serde(rename(serialize = "HTMLPart"))]
-    html_part: &'a str,
+    #[serde(
+        rename(serialize = "HTMLPart"),
+        skip_serializing_if = "Option::is_none"
+    )]
+    html_part: Option<&'a str>,
+    /// Shows up in the provider's logs and events, to correlate them with ours.
+    #[serde(
+        rename(serialize = "CustomID"),
+        skip_serializing_if = "Option::is_none"
+    )]
+    custom_id: Option<&'a str>,
 }
 
 #[derive(serde::Serialize)]
@@ -177,6 +254,30 @@ mod tests {
             .await;
     }
 
+    #[tokio::test]
+    async fn send_text_email_omits_html_part() {
+        let mock_server = MockServer::start().await;
+
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = email_client
+            .send_text_email(email(), &subject(), &content())
+            .await;
+        assert_ok!(result);
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        let msg = &body["Messages"][0];
+        assert!(msg.get("TextPart").is_some());
+        assert!(msg.get("HTMLPart").is_none());
+    }
+
     #[tokio::test]
     async fn send_email_succeeds_if_server_ret_200() {
         let mock_server = MockServer::start().await;
@@ -215,6 +316,27 @@ mod tests {
         assert_err!(result);
     }
 
+    #[tokio::test]
+    async fn probe_succeeds_if_server_responds_with_any_status() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        assert_ok!(email_client.probe().await);
+    }
+
+    #[tokio::test]
+    async fn probe_fails_if_server_is_unreachable() {
+        let email_client = email_client(String::from("http://127.0.0.1:1"));
+
+        assert_err!(email_client.probe().await);
+    }
+
     #[tokio::test]
     async fn send_email_times_out_if_resp_takes_long() {
         let mock_server = MockServer::start().await;
@@ -234,4 +356,30 @@ mod tests {
 
         assert_err!(result);
     }
+
+    #[tokio::test]
+    async fn timeout_changes_apply_to_existing_clones() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let clone = email_client.clone();
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(200)))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        assert_ok!(
+            clone
+                .send_email(email(), &subject(), &content(), &content())
+                .await
+        );
+        email_client.set_timeout(Duration::from_millis(50));
+        assert_eq!(clone.timeout(), Duration::from_millis(50));
+        assert_err!(
+            clone
+                .send_email(email(), &subject(), &content(), &content())
+                .await
+        );
+    }
 }
@@ -0,0 +1,237 @@
+use std::time::Duration;
+
+use sqlx::{types::chrono::Utc, Executor, PgPool, Postgres, Transaction};
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::{
+    configuration::OutboxSettings,
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    request_id::RequestId,
+    shutdown::Shutdown,
+    telemetry::{continue_trace, current_traceparent},
+};
+
+/// Queues an email for delivery by the outbox dispatcher. Written in the same
+/// transaction as the changes it tells the recipient about, it is sent if and
+/// only if they are committed.
+#[tracing::instrument(
+    name = "Queueing email in the outbox",
+    skip(transaction, recipient, html_part, text_part)
+)]
+pub async fn enqueue_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_part: Option<&str>,
+    text_part: &str,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    let request_id = RequestId::current();
+    let query = sqlx::query!(
+        r#"
+INSERT INTO email_outbox
+    (id, recipient, subject, html_part, text_part, request_id, traceparent, next_attempt_at, created_at)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $8)
+"#,
+        Uuid::new_v4(),
+        recipient.as_ref(),
+        subject,
+        html_part,
+        text_part,
+        request_id.as_ref().map(AsRef::as_ref),
+        current_traceparent(),
+        now,
+    );
+    transaction.execute(query).await.map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(())
+}
+
+pub enum DispatchOutcome {
+    /// An email was due and delivery was attempted.
+    Attempted,
+    EmptyQueue,
+}
+
+struct OutboxEntry {
+    id: Uuid,
+    recipient: String,
+    subject: String,
+    html_part: Option<String>,
+    text_part: String,
+    request_id: Option<String>,
+    traceparent: Option<String>,
+    attempts: i32,
+}
+
+/// Attempts the delivery of the next due email, if any. The entry is leased
+/// for the attempt so that concurrent dispatchers skip it; should this one
+/// die mid-attempt, the entry becomes due again once the lease expires.
+pub async fn try_dispatch_next(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    settings: &OutboxSettings,
+) -> Result<DispatchOutcome, sqlx::Error> {
+    let entry = match claim_entry(pool, settings.lease()).await? {
+        Some(entry) => entry,
+        None => return Ok(DispatchOutcome::EmptyQueue),
+    };
+
+    let span = tracing::info_span!(
+        "Delivering queued email",
+        outbox_entry_id = %entry.id,
+        attempt = entry.attempts + 1,
+        request_id = entry.request_id.as_deref(),
+    );
+    if let Some(traceparent) = &entry.traceparent {
+        continue_trace(&span, traceparent);
+    }
+    async {
+        match deliver(email_client, &entry).await {
+            Ok(()) => delete_entry(pool, entry.id).await,
+            Err(e) => {
+                let attempts = entry.attempts as u32 + 1;
+                if attempts >= settings.max_attempts {
+                    tracing::error!(
+                        error.cause_chain = %e,
+                        "Giving up on delivering a queued email after {} attempts",
+                        attempts
+                    );
+                    reschedule_entry(pool, entry.id, None, &e).await
+                } else {
+                    tracing::warn!(
+                        error.cause_chain = %e,
+                        "Failed to deliver a queued email, it will be retried"
+                    );
+                    let retry_in = settings.retry_backoff(attempts);
+                    reschedule_entry(pool, entry.id, Some(retry_in), &e).await
+                }
+            }
+        }
+    }
+    .instrument(span)
+    .await?;
+
+    Ok(DispatchOutcome::Attempted)
+}
+
+async fn deliver(email_client: &EmailClient, entry: &OutboxEntry) -> Result<(), String> {
+    let recipient = SubscriberEmail::parse(entry.recipient.clone())?;
+    let send = async {
+        match &entry.html_part {
+            Some(html_part) => {
+                email_client
+                    .send_email(recipient, &entry.subject, html_part, &entry.text_part)
+                    .await
+            }
+            None => {
+                email_client
+                    .send_text_email(recipient, &entry.subject, &entry.text_part)
+                    .await
+            }
+        }
+    };
+    let outcome = match entry.request_id.as_deref().and_then(RequestId::parse) {
+        Some(request_id) => request_id.scope(send).await,
+        None => send.await,
+    };
+    outcome.map_err(|e| e.to_string())
+}
+
+/// Leases the next due email for `lease`, in a transaction of its own.
+#[tracing::instrument(skip(pool))]
+async fn claim_entry(pool: &PgPool, lease: Duration) -> Result<Option<OutboxEntry>, sqlx::Error> {
+    let now = Utc::now();
+    let locked_until = now + chrono::Duration::from_std(lease).expect("Lease out of range");
+    sqlx::query_as!(
+        OutboxEntry,
+        r#"
+UPDATE email_outbox
+SET locked_until = $2
+WHERE id = (
+    SELECT id
+    FROM email_outbox
+    WHERE next_attempt_at <= $1 AND (locked_until IS NULL OR locked_until <= $1)
+    ORDER BY next_attempt_at
+    FOR UPDATE
+    SKIP LOCKED
+    LIMIT 1
+)
+RETURNING id, recipient, subject, html_part, text_part, request_id, traceparent, attempts
+"#,
+        now,
+        locked_until,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[tracing::instrument(skip(pool))]
+async fn delete_entry(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(r#"DELETE FROM email_outbox WHERE id = $1"#, id);
+    pool.execute(query).await.map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+/// Records a failed attempt, and when to try again, releasing the lease. No
+/// retry is scheduled when `retry_in` is `None`.
+#[tracing::instrument(skip(pool, error))]
+async fn reschedule_entry(
+    pool: &PgPool,
+    id: Uuid,
+    retry_in: Option<Duration>,
+    error: &str,
+) -> Result<(), sqlx::Error> {
+    let next_attempt_at = retry_in.map(|retry_in| {
+        Utc::now() + chrono::Duration::from_std(retry_in).expect("Retry backoff out of range")
+    });
+    let query = sqlx::query!(
+        r#"
+UPDATE email_outbox
+SET attempts = attempts + 1, next_attempt_at = $2, last_error = $3, locked_until = NULL
+WHERE id = $1
+"#,
+        id,
+        next_attempt_at,
+        error,
+    );
+    pool.execute(query).await.map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    Ok(())
+}
+
+/// Delivers queued emails until shutdown, polling for new ones once the
+/// outbox is drained. Meant to be run with [`Shutdown::spawn_worker`], on the
+/// application's primary pool so that it is closed once the worker is done.
+pub async fn run_dispatcher_until_stopped(
+    pool: PgPool,
+    email_client: EmailClient,
+    settings: OutboxSettings,
+    shutdown: Shutdown,
+) {
+    while !shutdown.is_triggered() {
+        match try_dispatch_next(&pool, &email_client, &settings).await {
+            Ok(DispatchOutcome::Attempted) => continue,
+            Ok(DispatchOutcome::EmptyQueue) => {}
+            Err(e) => tracing::error!("Failed to dispatch queued emails: {:?}", e),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(settings.poll_interval()) => {}
+            _ = shutdown.requested() => {}
+        }
+    }
+}
@@ -1,6 +1,15 @@
+pub mod authentication;
+pub mod cli;
+pub mod config_reload;
 pub mod configuration;
+pub mod db_pools;
 pub mod domain;
 pub mod email_client;
+pub mod email_outbox;
+pub mod listener;
+pub mod metrics;
+pub mod request_id;
 pub mod routes;
+pub mod shutdown;
 pub mod startup;
 pub mod telemetry;
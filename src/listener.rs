@@ -0,0 +1,189 @@
+use std::{
+    fmt,
+    net::{SocketAddr, TcpListener},
+    os::{
+        fd::{FromRawFd, IntoRawFd, RawFd},
+        unix::{fs::FileTypeExt, net::UnixListener},
+    },
+    path::PathBuf,
+};
+
+use crate::configuration::ApplicationSettings;
+
+/// The first file descriptor passed by systemd socket activation.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Where the app accepts connections.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl ListenAddress {
+    /// The TCP port, if listening on one.
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            Self::Tcp(address) => Some(address.port()),
+            Self::Unix(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "{}", address),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// The socket systemd passed by socket activation, following `sd_listen_fds`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SocketActivation {
+    fd: RawFd,
+}
+
+impl SocketActivation {
+    /// Reads `LISTEN_PID` and `LISTEN_FDS`, clearing them so that child
+    /// processes don't claim the socket. Call before any thread is spawned,
+    /// as changing the environment of a multi-threaded process is unsound.
+    pub fn take_from_env() -> Result<Option<Self>, std::io::Error> {
+        let pid = std::env::var("LISTEN_PID").ok();
+        let fds = std::env::var("LISTEN_FDS").ok();
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+
+        Self::parse(pid.as_deref(), fds.as_deref(), std::process::id())
+    }
+
+    fn parse(
+        pid: Option<&str>,
+        fds: Option<&str>,
+        own_pid: u32,
+    ) -> Result<Option<Self>, std::io::Error> {
+        let (Some(pid), Some(fds)) = (pid, fds) else {
+            return Ok(None);
+        };
+        if pid.parse() != Ok(own_pid) {
+            return Ok(None);
+        }
+        match fds.parse::<u32>() {
+            Ok(1) => Ok(Some(Self {
+                fd: SD_LISTEN_FDS_START,
+            })),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Expected a single socket from systemd, got LISTEN_FDS={}",
+                    fds
+                ),
+            )),
+        }
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// The socket passed by systemd when started by socket activation, the
+    /// Unix socket configured, or `host:port`, in that order.
+    pub fn bind(
+        settings: &ApplicationSettings,
+        socket_activation: Option<SocketActivation>,
+    ) -> Result<Self, std::io::Error> {
+        if let Some(socket_activation) = socket_activation {
+            return Ok(Self::from_systemd(socket_activation));
+        }
+        match &settings.unix_socket {
+            Some(path) => {
+                // A socket left behind by a previous run would fail the bind
+                if std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                    std::fs::remove_file(path)?;
+                }
+                Ok(Self::Unix(UnixListener::bind(path)?))
+            }
+            None => Ok(Self::Tcp(TcpListener::bind(format!(
+                "{}:{}",
+                settings.host, settings.port
+            ))?)),
+        }
+    }
+
+    /// Takes over the socket systemd passed us.
+    fn from_systemd(socket_activation: SocketActivation) -> Self {
+        // SAFETY: with LISTEN_PID naming this process, systemd guarantees the
+        // descriptor is an open socket handed over to us alone, and taking
+        // the activation out of the environment means it is only used once
+        let listener = unsafe { UnixListener::from_raw_fd(socket_activation.fd) };
+        match listener.local_addr() {
+            Ok(_) => Self::Unix(listener),
+            // SAFETY: the descriptor was released by the Unix listener
+            Err(_) => Self::Tcp(unsafe { TcpListener::from_raw_fd(listener.into_raw_fd()) }),
+        }
+    }
+
+    pub fn address(&self) -> Result<ListenAddress, std::io::Error> {
+        match self {
+            Self::Tcp(listener) => Ok(ListenAddress::Tcp(listener.local_addr()?)),
+            Self::Unix(listener) => {
+                let address = listener.local_addr()?;
+                let path = address.as_pathname().ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "Unnamed Unix sockets are not supported",
+                    )
+                })?;
+                Ok(ListenAddress::Unix(path.to_path_buf()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok_eq};
+
+    use super::{ListenAddress, SocketActivation, SD_LISTEN_FDS_START};
+
+    #[test]
+    fn addresses_are_displayed_by_kind() {
+        let tcp = ListenAddress::Tcp("127.0.0.1:8000".parse().unwrap());
+        let unix = ListenAddress::Unix("/run/zero2prod.sock".into());
+
+        assert_eq!(tcp.to_string(), "127.0.0.1:8000");
+        assert_eq!(tcp.port(), Some(8000));
+        assert_eq!(unix.to_string(), "unix:/run/zero2prod.sock");
+        assert_eq!(unix.port(), None);
+    }
+
+    #[test]
+    fn the_socket_is_only_taken_when_passed_to_this_process() {
+        let parse = |pid, fds| SocketActivation::parse(pid, fds, 42);
+
+        assert_ok_eq!(
+            parse(Some("42"), Some("1")),
+            Some(SocketActivation {
+                fd: SD_LISTEN_FDS_START
+            })
+        );
+        assert_ok_eq!(parse(Some("41"), Some("1")), None);
+        assert_ok_eq!(parse(None, Some("1")), None);
+        assert_ok_eq!(parse(Some("42"), None), None);
+        assert_ok_eq!(parse(Some("not-a-pid"), Some("1")), None);
+    }
+
+    #[test]
+    fn exactly_one_socket_is_expected() {
+        let parse = |fds| SocketActivation::parse(Some("42"), Some(fds), 42);
+
+        assert_err!(parse("2"));
+        assert_err!(parse("0"));
+        assert_err!(parse("one"));
+    }
+}
@@ -1,22 +1,101 @@
+use clap::Parser;
 use zero2prod::{
-    configuration::get_config,
+    cli::{check_config, create_admin, migrate, send_test_email, Cli, Command, ConfigCommand},
+    configuration::{get_config, load_config, ConfigErrors, Environment, Settings},
+    email_outbox::run_dispatcher_until_stopped,
+    listener::SocketActivation,
     startup::Application,
-    telemetry::{get_subscriber, init_subscriber},
+    telemetry::{get_subscriber, get_tracer, init_subscriber},
 };
 
-#[tokio::main]
-async fn main() -> Result<(), std::io::Error> {
-    let subscriber = get_subscriber(
+fn main() -> Result<(), std::io::Error> {
+    let command = Cli::parse().command.unwrap_or(Command::Serve);
+    // The environment can only be changed safely before the runtime spawns
+    // its worker threads
+    let socket_activation = match command {
+        Command::Serve => SocketActivation::take_from_env()?,
+        _ => None,
+    };
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(command, socket_activation))
+}
+
+async fn run(
+    command: Command,
+    socket_activation: Option<SocketActivation>,
+) -> Result<(), std::io::Error> {
+    match command {
+        Command::Serve => serve(socket_activation).await,
+        Command::Migrate { dry_run } => {
+            exit_on_error(migrate(read_config_for(Settings::validate_database), dry_run).await)
+        }
+        Command::CreateAdmin { username } => exit_on_error(
+            create_admin(read_config_for(Settings::validate_database), username).await,
+        ),
+        Command::SendTestEmail { address } => exit_on_error(
+            send_test_email(read_config_for(Settings::validate_email_client), address).await,
+        ),
+        Command::Config {
+            command: ConfigCommand::Check,
+        } => {
+            if !check_config() {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+    }
+}
+
+fn read_config() -> Settings {
+    get_config().expect("Failed to read config")
+}
+
+/// Reads the configuration, only checking the settings a command uses with
+/// `validate`, so that it runs with the rest of them unset or invalid.
+fn read_config_for(validate: fn(&Settings, &Environment) -> Result<(), ConfigErrors>) -> Settings {
+    let (config, environment) = load_config().expect("Failed to read config");
+    if let Err(e) = validate(&config, &environment) {
+        panic!("Failed to read config: {}", e);
+    }
+    config
+}
+
+fn exit_on_error(outcome: Result<(), String>) -> Result<(), std::io::Error> {
+    if let Err(e) = outcome {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+async fn serve(socket_activation: Option<SocketActivation>) -> Result<(), std::io::Error> {
+    let config = read_config();
+
+    let tracer = get_tracer(&config.telemetry).expect("Failed to build tracer");
+    let (subscriber, log_filter) = get_subscriber(
         String::from("zero2prod"),
-        String::from("info"),
+        config.telemetry.log_level.clone(),
         std::io::stdout,
+        tracer,
+        config.telemetry.redaction.clone(),
     );
     init_subscriber(subscriber);
 
-    let config = get_config().expect("Failed to read config");
-    let application = Application::build(config)
+    let application = Application::build(config.clone(), log_filter, socket_activation)
         .await
         .expect("Failed to build Application");
-    application.run_until_stopped().await?;
-    Ok(())
+    tracing::info!("Listening on {}", application.address());
+    let shutdown = application.shutdown();
+    shutdown.spawn_worker(run_dispatcher_until_stopped(
+        application.db_pool(),
+        application.email_client(),
+        config.outbox,
+        shutdown.clone(),
+    ));
+    let result = application.run_until_stopped().await;
+    // Export the spans recorded while draining before exiting
+    opentelemetry::global::shutdown_tracer_provider();
+    result
 }
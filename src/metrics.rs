@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use sqlx::PgPool;
+
+pub static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+pub static HTTP_REQUESTS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("http_requests_total", "HTTP requests handled, by route"),
+        &["method", "route", "status"],
+    ))
+});
+
+pub static HTTP_REQUEST_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new(
+            "http_request_duration_seconds",
+            "Time spent handling HTTP requests, by route",
+        ),
+        &["method", "route"],
+    ))
+});
+
+pub static DB_POOL_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new(
+        "db_pool_connections",
+        "Connections currently open in the database pool",
+    ))
+});
+
+pub static DB_POOL_IDLE_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new(
+        "db_pool_idle_connections",
+        "Idle connections in the database pool",
+    ))
+});
+
+pub static DB_POOL_ACQUIRE_WAIT_SECONDS: Lazy<Histogram> = Lazy::new(|| {
+    register(Histogram::with_opts(HistogramOpts::new(
+        "db_pool_acquire_wait_seconds",
+        "Time waited for a pooled connection",
+    )))
+});
+
+pub static EMAILS_SENT_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "emails_sent_total",
+            "Emails handed to the email API, by outcome",
+        ),
+        &["outcome"],
+    ))
+});
+
+pub static EMAIL_SEND_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new(
+            "email_send_duration_seconds",
+            "Time spent calling the email API, by outcome",
+        ),
+        &["outcome"],
+    ))
+});
+
+pub static SUBSCRIPTIONS_CREATED_TOTAL: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new(
+        "subscriptions_created_total",
+        "New subscribers stored pending confirmation",
+    ))
+});
+
+pub static CONFIRMATIONS_COMPLETED_TOTAL: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new(
+        "confirmations_completed_total",
+        "Subscriptions confirmed through their confirmation link",
+    ))
+});
+
+fn register<M>(metric: prometheus::Result<M>) -> M
+where
+    M: prometheus::core::Collector + Clone + 'static,
+{
+    let metric = metric.expect("Invalid metric definition");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Failed to register metric");
+    metric
+}
+
+pub fn record_http_request(method: &str, route: &str, status: u16, elapsed: Duration) {
+    HTTP_REQUESTS_TOTAL
+        .with_label_values(&[method, route, &status.to_string()])
+        .inc();
+    HTTP_REQUEST_DURATION_SECONDS
+        .with_label_values(&[method, route])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn record_email_sent(success: bool, elapsed: Duration) {
+    let outcome = if success { "success" } else { "failure" };
+    EMAILS_SENT_TOTAL.with_label_values(&[outcome]).inc();
+    EMAIL_SEND_DURATION_SECONDS
+        .with_label_values(&[outcome])
+        .observe(elapsed.as_secs_f64());
+}
+
+#[tracing::instrument(name = "Render metrics", skip(db_pool))]
+pub async fn get_metrics(db_pool: web::Data<PgPool>) -> HttpResponse {
+    DB_POOL_CONNECTIONS.set(db_pool.size() as i64);
+    DB_POOL_IDLE_CONNECTIONS.set(db_pool.num_idle() as i64);
+
+    // Make sure every metric shows up even before it is first recorded
+    Lazy::force(&HTTP_REQUESTS_TOTAL);
+    Lazy::force(&HTTP_REQUEST_DURATION_SECONDS);
+    Lazy::force(&DB_POOL_ACQUIRE_WAIT_SECONDS);
+    Lazy::force(&EMAILS_SENT_TOTAL);
+    Lazy::force(&EMAIL_SEND_DURATION_SECONDS);
+    Lazy::force(&SUBSCRIPTIONS_CREATED_TOTAL);
+    Lazy::force(&CONFIRMATIONS_COMPLETED_TOTAL);
+
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+        tracing::error!("Failed to encode metrics: {:?}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::plaintext())
+        .body(buffer)
+}
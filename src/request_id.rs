@@ -0,0 +1,158 @@
+use std::future::{ready, Future, Ready};
+
+use actix_web::{
+    body::{to_bytes, BoxBody, MessageBody},
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    error::ErrorInternalServerError,
+    http::header::{HeaderName, HeaderValue, CONTENT_TYPE},
+    Error, FromRequest, HttpMessage, HttpRequest,
+};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+tokio::task_local! {
+    static CURRENT_REQUEST_ID: RequestId;
+}
+
+/// Identifies a request across our logs, our responses and the services we
+/// call. Taken from the caller's `X-Request-Id` header when it is sensible,
+/// generated otherwise.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    pub fn generate() -> Self {
+        Self(Uuid::new_v4().to_string())
+    }
+
+    /// Accepts upstream ids of up to 128 URL-safe characters, so they can be
+    /// logged and echoed back verbatim.
+    pub fn parse(s: &str) -> Option<Self> {
+        let is_valid = !s.is_empty()
+            && s.len() <= 128
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+        is_valid.then(|| Self(s.to_string()))
+    }
+
+    /// Takes the id from the request headers, or generates one, and stores it
+    /// in the request extensions for the rest of the pipeline.
+    pub fn assign(request: &ServiceRequest) -> Self {
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Self::parse)
+            .unwrap_or_else(Self::generate);
+        request.extensions_mut().insert(request_id.clone());
+        request_id
+    }
+
+    /// The id of the request being handled by the current task, if any.
+    pub fn current() -> Option<Self> {
+        CURRENT_REQUEST_ID.try_with(Clone::clone).ok()
+    }
+
+    /// Runs `f` with this id as the current one.
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        CURRENT_REQUEST_ID.scope(self, f).await
+    }
+}
+
+impl AsRef<str> for RequestId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromRequest for RequestId {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let request_id = req
+            .extensions()
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_else(Self::generate);
+        ready(Ok(request_id))
+    }
+}
+
+/// Returns the request id in the response headers and, for JSON error
+/// responses, as a `request_id` field of the body.
+pub async fn attach_request_id<B>(
+    response: ServiceResponse<B>,
+) -> Result<ServiceResponse<BoxBody>, Error>
+where
+    B: MessageBody + 'static,
+{
+    let mut response = response.map_into_boxed_body();
+    let request_id = response.request().extensions().get::<RequestId>().cloned();
+    let request_id = match request_id {
+        Some(request_id) => request_id,
+        None => return Ok(response),
+    };
+    response.headers_mut().insert(
+        REQUEST_ID_HEADER,
+        HeaderValue::from_str(request_id.as_ref()).expect("Request ids are valid header values"),
+    );
+
+    let is_json_error = (response.status().is_client_error()
+        || response.status().is_server_error())
+        && response.headers().get(CONTENT_TYPE).is_some_and(|value| {
+            value.as_bytes().starts_with(b"application/json")
+                || value.as_bytes().starts_with(b"application/problem+json")
+        });
+    if !is_json_error {
+        return Ok(response);
+    }
+
+    let (request, response) = response.into_parts();
+    let (response, body) = response.into_parts();
+    let body = to_bytes(body).await.map_err(ErrorInternalServerError)?;
+    let body = match serde_json::from_slice::<serde_json::Value>(&body) {
+        Ok(serde_json::Value::Object(mut fields)) => {
+            fields.insert("request_id".into(), request_id.to_string().into());
+            serde_json::to_vec(&fields).expect("Failed to serialize error body")
+        }
+        _ => body.to_vec(),
+    };
+    Ok(ServiceResponse::new(
+        request,
+        response.set_body(BoxBody::new(body)),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_none, assert_some};
+
+    use super::RequestId;
+
+    #[test]
+    fn upstream_ids_are_accepted() {
+        for id in &[
+            "abc-123",
+            "req_42.retry:1",
+            &uuid::Uuid::new_v4().to_string(),
+        ] {
+            assert_some!(RequestId::parse(id));
+        }
+    }
+
+    #[test]
+    fn unsafe_or_oversized_ids_are_rejected() {
+        let too_long = "a".repeat(129);
+        for id in &["", "a b", "id\nFAKE LOG LINE", "<script>", &too_long] {
+            assert_none!(RequestId::parse(id));
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::authenticate_admin;
+use crate::domain::ListSlug;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct NewListBody {
+    slug: String,
+    name: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct ListRecord {
+    id: Uuid,
+    slug: String,
+    name: String,
+    created_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "List mailing lists", skip(request, db_pool))]
+pub async fn get_lists(request: HttpRequest, db_pool: web::Data<PgPool>) -> HttpResponse {
+    if let Err(response) = authenticate_admin(&request, &db_pool).await {
+        return response;
+    }
+
+    let lists = sqlx::query_as!(
+        ListRecord,
+        r#"SELECT id, slug, name, created_at FROM lists ORDER BY slug"#
+    )
+    .fetch_all(db_pool.get_ref())
+    .await;
+
+    match lists {
+        Ok(lists) => HttpResponse::Ok().json(lists),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "Create a mailing list", skip(request, db_pool))]
+pub async fn create_list(
+    request: HttpRequest,
+    body: web::Json<NewListBody>,
+    db_pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if let Err(response) = authenticate_admin(&request, &db_pool).await {
+        return response;
+    }
+
+    let body = body.into_inner();
+    let slug = match ListSlug::parse(body.slug) {
+        Ok(slug) => slug,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+    if body.name.trim().is_empty() {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    let list = ListRecord {
+        id: Uuid::new_v4(),
+        slug: slug.as_ref().to_string(),
+        name: body.name,
+        created_at: Utc::now(),
+    };
+    let result = sqlx::query!(
+        r#"INSERT INTO lists (id, slug, name, created_at)
+        VALUES ($1, $2, $3, $4)"#,
+        list.id,
+        list.slug,
+        list.name,
+        list.created_at,
+    )
+    .execute(db_pool.get_ref())
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Created().json(list),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            HttpResponse::Conflict().finish()
+        }
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
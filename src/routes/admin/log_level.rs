@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+
+use super::authenticate_admin;
+use crate::telemetry::LogFilter;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct LogLevelBody {
+    /// An env-filter directive, e.g. `info,zero2prod=debug`.
+    directive: String,
+    /// Restore the previous filter after this many seconds.
+    ttl_seconds: Option<u64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct LogLevelRecord {
+    directive: String,
+}
+
+#[tracing::instrument(name = "Get log level", skip(request, db_pool, log_filter))]
+pub async fn get_log_level(
+    request: HttpRequest,
+    db_pool: web::Data<PgPool>,
+    log_filter: web::Data<LogFilter>,
+) -> HttpResponse {
+    if let Err(response) = authenticate_admin(&request, &db_pool).await {
+        return response;
+    }
+
+    match log_filter.current() {
+        Ok(directive) => HttpResponse::Ok().json(LogLevelRecord { directive }),
+        Err(e) => {
+            tracing::error!("Failed to read log filter: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "Set log level", skip(request, db_pool, log_filter))]
+pub async fn put_log_level(
+    request: HttpRequest,
+    body: web::Json<LogLevelBody>,
+    db_pool: web::Data<PgPool>,
+    log_filter: web::Data<LogFilter>,
+) -> HttpResponse {
+    let admin_id = match authenticate_admin(&request, &db_pool).await {
+        Ok(admin_id) => admin_id,
+        Err(response) => return response,
+    };
+
+    let ttl = match body.ttl_seconds {
+        Some(0) => return HttpResponse::BadRequest().body("ttl_seconds must be positive"),
+        ttl_seconds => ttl_seconds.map(Duration::from_secs),
+    };
+
+    if let Err(e) = log_filter.set(&body.directive, ttl) {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    tracing::info!(
+        target: "audit",
+        %admin_id,
+        directive = %body.directive,
+        ttl_seconds = ?body.ttl_seconds,
+        "Log level changed"
+    );
+
+    HttpResponse::Ok().json(LogLevelRecord {
+        directive: body.into_inner().directive,
+    })
+}
@@ -0,0 +1,49 @@
+mod lists;
+mod log_level;
+mod newsletters;
+mod segments;
+mod subscribers;
+
+pub use lists::*;
+pub use log_level::*;
+pub use newsletters::*;
+pub use segments::*;
+pub use subscribers::*;
+
+use actix_web::{http::header, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::{basic_authentication, validate_credentials, AuthError};
+
+/// Resolves the admin user making `request`, or the response to reject it with.
+pub async fn authenticate_admin(
+    request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<Uuid, HttpResponse> {
+    let unauthorized = || {
+        HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, r#"Basic realm="admin""#))
+            .finish()
+    };
+
+    let credentials = match basic_authentication(request.headers()) {
+        Ok(credentials) => credentials,
+        Err(e) => {
+            tracing::warn!("Rejected admin request: {}", e);
+            return Err(unauthorized());
+        }
+    };
+
+    match validate_credentials(credentials, pool).await {
+        Ok(user_id) => Ok(user_id),
+        Err(AuthError::InvalidCredentials(e)) => {
+            tracing::warn!("Rejected admin request: {}", e);
+            Err(unauthorized())
+        }
+        Err(AuthError::UnexpectedError(e)) => {
+            tracing::error!("Failed to validate admin credentials: {}", e);
+            Err(HttpResponse::InternalServerError().finish())
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use super::{authenticate_admin, get_segment};
+use crate::{
+    db_pools::begin_transaction,
+    domain::{EmailFormat, ListSlug, Segment, SubscriberEmail},
+    email_outbox::enqueue_email,
+    routes::{get_list_ids, preferences_footer_html, preferences_footer_text},
+    startup::ApplicationBaseUrl,
+};
+
+#[derive(serde::Deserialize, Debug)]
+pub struct NewsletterBody {
+    list: String,
+    /// Name of a segment narrowing down which confirmed subscribers of the list receive the issue.
+    segment: Option<String>,
+    title: String,
+    content: Content,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct Content {
+    html: String,
+    text: String,
+}
+
+struct ConfirmedSubscriber {
+    email: SubscriberEmail,
+    email_format: EmailFormat,
+    preferences_token: String,
+}
+
+#[derive(sqlx::FromRow)]
+struct ConfirmedSubscriberRow {
+    email: String,
+    email_format: String,
+    preferences_token: String,
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(request, body, db_pool, base_url),
+    fields(list = %body.list, segment = ?body.segment)
+)]
+pub async fn publish_newsletter(
+    request: HttpRequest,
+    body: web::Json<NewsletterBody>,
+    db_pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    if let Err(response) = authenticate_admin(&request, &db_pool).await {
+        return response;
+    }
+
+    let body = body.into_inner();
+    let list = match ListSlug::parse(body.list) {
+        Ok(list) => list,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+
+    let list_id = match get_list_ids(&db_pool, &[list]).await {
+        Ok(list_ids) => match list_ids.first() {
+            Some(list_id) => *list_id,
+            None => return HttpResponse::NotFound().finish(),
+        },
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let segment = match &body.segment {
+        Some(name) => match get_segment(&db_pool, name).await {
+            Ok(Some(segment)) => Some(segment),
+            Ok(None) => return HttpResponse::NotFound().finish(),
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        },
+        None => None,
+    };
+
+    let subscribers = match get_confirmed_subscribers(&db_pool, list_id, segment.as_ref()).await {
+        Ok(subscribers) => subscribers,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    // Issues are delivered by the outbox workers, all of them or none
+    let mut transaction = match begin_transaction(&db_pool).await {
+        Ok(txn) => txn,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    for subscriber in subscribers {
+        match subscriber {
+            Ok(subscriber) => {
+                let text_part = format!(
+                    "{}{}",
+                    body.content.text,
+                    preferences_footer_text(&base_url.0, &subscriber.preferences_token)
+                );
+                let html_part = match subscriber.email_format {
+                    EmailFormat::Html => Some(format!(
+                        "{}{}",
+                        body.content.html,
+                        preferences_footer_html(&base_url.0, &subscriber.preferences_token)
+                    )),
+                    EmailFormat::Text => None,
+                };
+                if enqueue_email(
+                    &mut transaction,
+                    &subscriber.email,
+                    &body.title,
+                    html_part.as_deref(),
+                    &text_part,
+                )
+                .await
+                .is_err()
+                {
+                    return HttpResponse::InternalServerError().finish();
+                }
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Skipping a confirmed subscriber. Their stored contact details are invalid: {}",
+                    e
+                );
+            }
+        }
+    }
+
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[tracing::instrument(name = "Get confirmed subscribers of a list", skip(pool))]
+async fn get_confirmed_subscribers(
+    pool: &PgPool,
+    list_id: Uuid,
+    segment: Option<&Segment>,
+) -> Result<Vec<Result<ConfirmedSubscriber, String>>, sqlx::Error> {
+    let mut query = QueryBuilder::<Postgres>::new(
+        r#"SELECT subscriptions.email, subscriptions.email_format, subscriptions.preferences_token
+        FROM subscriptions
+        JOIN list_subscriptions ON list_subscriptions.subscriber_id = subscriptions.id
+        WHERE list_subscriptions.status = 'confirmed' AND list_subscriptions.list_id = "#,
+    );
+    query.push_bind(list_id);
+    if let Some(segment) = segment {
+        query.push(" AND ");
+        segment.push_sql(&mut query);
+    }
+
+    let rows = query
+        .build_query_as::<ConfirmedSubscriberRow>()
+        .fetch_all(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+
+    let confirmed_subscribers = rows
+        .into_iter()
+        .map(|r| {
+            Ok(ConfirmedSubscriber {
+                email: SubscriberEmail::parse(r.email)?,
+                email_format: EmailFormat::try_from(r.email_format)?,
+                preferences_token: r.preferences_token,
+            })
+        })
+        .collect();
+
+    Ok(confirmed_subscribers)
+}
@@ -0,0 +1,109 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use super::authenticate_admin;
+use crate::domain::Segment;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct NewSegmentBody {
+    name: String,
+    expression: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct SegmentRecord {
+    name: String,
+    expression: String,
+    created_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "List segments", skip(request, db_pool))]
+pub async fn get_segments(request: HttpRequest, db_pool: web::Data<PgPool>) -> HttpResponse {
+    if let Err(response) = authenticate_admin(&request, &db_pool).await {
+        return response;
+    }
+
+    let segments = sqlx::query_as!(
+        SegmentRecord,
+        r#"SELECT name, expression, created_at FROM segments ORDER BY name"#
+    )
+    .fetch_all(db_pool.get_ref())
+    .await;
+
+    match segments {
+        Ok(segments) => HttpResponse::Ok().json(segments),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "Create a segment", skip(request, db_pool))]
+pub async fn create_segment(
+    request: HttpRequest,
+    body: web::Json<NewSegmentBody>,
+    db_pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if let Err(response) = authenticate_admin(&request, &db_pool).await {
+        return response;
+    }
+
+    let body = body.into_inner();
+    let name = body.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > 64 {
+        return HttpResponse::BadRequest().body("Segment names must be 1 to 64 characters long");
+    }
+    if let Err(e) = Segment::parse(&body.expression) {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    let segment = SegmentRecord {
+        name,
+        expression: body.expression,
+        created_at: Utc::now(),
+    };
+    let result = sqlx::query!(
+        r#"INSERT INTO segments (name, expression, created_at)
+        VALUES ($1, $2, $3)"#,
+        segment.name,
+        segment.expression,
+        segment.created_at,
+    )
+    .execute(db_pool.get_ref())
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Created().json(segment),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            HttpResponse::Conflict().finish()
+        }
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Looks up and parses a named segment.
+#[tracing::instrument(name = "Get segment by name", skip(pool))]
+pub async fn get_segment(pool: &PgPool, name: &str) -> Result<Option<Segment>, sqlx::Error> {
+    let row = sqlx::query!(r#"SELECT expression FROM segments WHERE name = $1"#, name)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+
+    // Expressions are validated before being stored, but may no longer parse
+    // once the grammar changes
+    row.map(|r| {
+        Segment::parse(&r.expression).map_err(|e| {
+            tracing::error!("Stored segment {} is invalid: {}", name, e);
+            sqlx::Error::Decode(e.into())
+        })
+    })
+    .transpose()
+}
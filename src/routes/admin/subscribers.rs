@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use actix_web::{
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web, HttpRequest, HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use sqlx::{types::Json, PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use super::authenticate_admin;
+use crate::{db_pools::DbPools, domain::SubscriberAttributes, shutdown::Shutdown};
+
+/// Filters shared by the subscriber listing and export endpoints.
+#[derive(serde::Deserialize, Debug)]
+pub struct SubscriberFilters {
+    status: Option<String>,
+    subscribed_after: Option<DateTime<Utc>>,
+    subscribed_before: Option<DateTime<Utc>>,
+    list: Option<String>,
+}
+
+impl SubscriberFilters {
+    /// Selects the subscribers matching the filters, in the order they subscribed.
+    fn select_subscribers(&self) -> QueryBuilder<'static, Postgres> {
+        let mut query = QueryBuilder::new(
+            "SELECT id, email, name, subscribed_at, status FROM subscriptions WHERE TRUE",
+        );
+        if let Some(status) = &self.status {
+            query.push(" AND status = ").push_bind(status.clone());
+        }
+        if let Some(subscribed_after) = self.subscribed_after {
+            query
+                .push(" AND subscribed_at >= ")
+                .push_bind(subscribed_after);
+        }
+        if let Some(subscribed_before) = self.subscribed_before {
+            query
+                .push(" AND subscribed_at < ")
+                .push_bind(subscribed_before);
+        }
+        if let Some(list) = &self.list {
+            query
+                .push(
+                    " AND EXISTS (
+                        SELECT 1 FROM list_subscriptions
+                        JOIN lists ON lists.id = list_subscriptions.list_id
+                        WHERE list_subscriptions.subscriber_id = subscriptions.id
+                        AND lists.slug = ",
+                )
+                .push_bind(list.clone())
+                .push(")");
+        }
+        query.push(" ORDER BY subscribed_at, id");
+        query
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct Pagination {
+    #[serde(default = "default_limit")]
+    limit: i64,
+    #[serde(default)]
+    offset: i64,
+}
+
+fn default_limit() -> i64 {
+    100
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ExportParameters {
+    format: ExportFormat,
+}
+
+#[derive(serde::Serialize, sqlx::FromRow)]
+pub struct SubscriberRecord {
+    id: Uuid,
+    email: String,
+    name: String,
+    subscribed_at: DateTime<Utc>,
+    status: String,
+}
+
+#[tracing::instrument(name = "List subscribers", skip(request, db_pools))]
+pub async fn list_subscribers(
+    request: HttpRequest,
+    filters: web::Query<SubscriberFilters>,
+    pagination: web::Query<Pagination>,
+    db_pools: web::Data<DbPools>,
+) -> HttpResponse {
+    if let Err(response) = authenticate_admin(&request, db_pools.writer()).await {
+        return response;
+    }
+
+    let limit = pagination.limit.clamp(1, 1000);
+    let offset = pagination.offset.max(0);
+
+    let mut query = filters.select_subscribers();
+    query.push(" LIMIT ").push_bind(limit);
+    query.push(" OFFSET ").push_bind(offset);
+    let subscribers = query
+        .build_query_as::<SubscriberRecord>()
+        .fetch_all(db_pools.reader())
+        .await;
+
+    match subscribers {
+        Ok(subscribers) => HttpResponse::Ok().json(subscribers),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[tracing::instrument(name = "Export subscribers", skip(request, db_pools, shutdown))]
+pub async fn export_subscribers(
+    request: HttpRequest,
+    parameters: web::Query<ExportParameters>,
+    filters: web::Query<SubscriberFilters>,
+    db_pools: web::Data<DbPools>,
+    shutdown: web::Data<Shutdown>,
+) -> HttpResponse {
+    let admin_id = match authenticate_admin(&request, db_pools.writer()).await {
+        Ok(admin_id) => admin_id,
+        Err(response) => return response,
+    };
+
+    let format = parameters.format;
+    let filters = filters.into_inner();
+
+    tracing::info!(
+        target: "audit",
+        %admin_id,
+        ?format,
+        ?filters,
+        "Subscriber list exported"
+    );
+
+    let pool = db_pools.reader().clone();
+    // The handler returns before the body is sent, so shutdown has to wait
+    // for the stream itself
+    let in_flight = shutdown.track_response();
+    let body = async_stream::try_stream! {
+        let _in_flight = in_flight;
+        if let Some(header) = format.header() {
+            yield web::Bytes::from_static(header);
+        }
+
+        let mut query = filters.select_subscribers();
+        let mut rows = query
+            .build_query_as::<SubscriberRecord>()
+            .fetch(&pool)
+            .map_err(|e| {
+                tracing::error!("Failed to stream subscribers: {:?}", e);
+                e
+            });
+
+        while let Some(record) = rows.try_next().await? {
+            yield format.encode(&record);
+        }
+    };
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format.file_name().to_string())],
+        })
+        .streaming::<_, sqlx::Error>(body)
+}
+
+#[tracing::instrument(name = "Set subscriber attributes", skip(request, body, db_pool))]
+pub async fn put_subscriber_attributes(
+    request: HttpRequest,
+    subscriber_id: web::Path<Uuid>,
+    body: web::Json<HashMap<String, String>>,
+    db_pool: web::Data<PgPool>,
+) -> HttpResponse {
+    if let Err(response) = authenticate_admin(&request, &db_pool).await {
+        return response;
+    }
+
+    let attributes = match SubscriberAttributes::parse(body.into_inner()) {
+        Ok(attributes) => attributes,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let result = sqlx::query!(
+        r#"UPDATE subscriptions SET attributes = $1 WHERE id = $2"#,
+        Json(&attributes) as _,
+        subscriber_id.into_inner(),
+    )
+    .execute(db_pool.get_ref())
+    .await;
+
+    match result {
+        Ok(result) if result.rows_affected() == 0 => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::Ok().json(attributes),
+        Err(e) => {
+            tracing::error!("Failed to execute query: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+impl ExportFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Jsonl => "application/jsonl",
+        }
+    }
+
+    fn file_name(&self) -> &'static str {
+        match self {
+            Self::Csv => "subscribers.csv",
+            Self::Jsonl => "subscribers.jsonl",
+        }
+    }
+
+    fn header(&self) -> Option<&'static [u8]> {
+        match self {
+            Self::Csv => Some(b"id,email,name,subscribed_at,status\n"),
+            Self::Jsonl => None,
+        }
+    }
+
+    fn encode(&self, record: &SubscriberRecord) -> web::Bytes {
+        let mut line = match self {
+            Self::Csv => [
+                record.id.to_string(),
+                csv_field(&record.email),
+                csv_field(&record.name),
+                record.subscribed_at.to_rfc3339(),
+                csv_field(&record.status),
+            ]
+            .join(","),
+            Self::Jsonl => {
+                serde_json::to_string(record).expect("Failed to serialize subscriber record")
+            }
+        };
+        line.push('\n');
+        web::Bytes::from(line)
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    // Spreadsheets run cells starting with these as formulas
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::csv_field;
+
+    #[test]
+    fn plain_csv_fields_are_left_untouched() {
+        assert_eq!(csv_field("bobby@gmail.com"), "bobby@gmail.com");
+    }
+
+    #[test]
+    fn csv_fields_with_separators_are_quoted() {
+        assert_eq!(csv_field("Portis, Bobby"), "\"Portis, Bobby\"");
+        assert_eq!(csv_field("line\nbreak"), "\"line\nbreak\"");
+    }
+
+    #[test]
+    fn csv_fields_starting_like_formulas_are_escaped() {
+        assert_eq!(csv_field("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(csv_field("+1"), "'+1");
+        assert_eq!(csv_field("-1"), "'-1");
+        assert_eq!(csv_field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(csv_field("\tcmd"), "'\tcmd");
+        assert_eq!(csv_field("\rcmd"), "\"'\rcmd\"");
+        assert_eq!(csv_field("a=b"), "a=b");
+    }
+
+    #[test]
+    fn quotes_in_csv_fields_are_escaped() {
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
@@ -0,0 +1,75 @@
+use actix_web::{
+    error::{InternalError, JsonPayloadError},
+    http::StatusCode,
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+
+/// An RFC 7807 problem details body, served as `application/problem+json`.
+#[derive(serde::Serialize, Debug)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    #[serde(rename = "invalid-params", skip_serializing_if = "Vec::is_empty")]
+    invalid_params: Vec<InvalidParam>,
+}
+
+/// A request field that failed validation, and why.
+#[derive(serde::Serialize, Debug)]
+pub struct InvalidParam {
+    pub name: &'static str,
+    pub reason: String,
+}
+
+impl Problem {
+    /// A problem without a more specific type than its status code.
+    pub fn new(status: StatusCode, detail: impl Into<String>) -> Self {
+        Self {
+            kind: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            detail: detail.into(),
+            invalid_params: Vec::new(),
+        }
+    }
+
+    pub fn invalid_params(invalid_params: Vec<InvalidParam>) -> Self {
+        Self::new(
+            StatusCode::BAD_REQUEST,
+            "The request has invalid parameters",
+        )
+        .with_invalid_params(invalid_params)
+    }
+
+    /// Points at the request fields the problem is about.
+    pub fn with_invalid_params(self, invalid_params: Vec<InvalidParam>) -> Self {
+        Self {
+            invalid_params,
+            ..self
+        }
+    }
+
+    pub fn internal_server_error() -> Self {
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "The request could not be processed",
+        )
+    }
+
+    pub fn response(&self) -> HttpResponse {
+        HttpResponse::build(StatusCode::from_u16(self.status).unwrap())
+            .content_type("application/problem+json")
+            .json(self)
+    }
+}
+
+/// Rejects JSON bodies that can't be read with a problem rather than the
+/// plain text error actix answers with.
+pub fn problem_json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|e: JsonPayloadError, _: &HttpRequest| {
+        let response = Problem::new(e.status_code(), e.to_string()).response();
+        InternalError::from_response(e, response).into()
+    })
+}
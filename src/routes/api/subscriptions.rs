@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use actix_web::{http::StatusCode, web, HttpResponse};
+use serde_json::Value;
+use sqlx::PgPool;
+
+use super::{InvalidParam, Problem};
+use crate::domain::{
+    ListSlug, NewSubscriber, SubscriberAttributes, SubscriberEmail, SubscriberName,
+};
+use crate::routes::{subscribe, SubscribeError};
+use crate::startup::ApplicationBaseUrl;
+
+/// Fields are read as any JSON value, so that one of the wrong type is
+/// reported along with the other invalid fields instead of failing the
+/// whole body.
+#[derive(serde::Deserialize, Debug)]
+pub struct SubscribeRequest {
+    /// Missing or null names and emails are reported as invalid, like empty ones.
+    #[serde(default)]
+    name: Value,
+    #[serde(default)]
+    email: Value,
+    /// Slugs of the lists to join, defaults to the main newsletter.
+    #[serde(default)]
+    lists: Value,
+    #[serde(default)]
+    attributes: Value,
+}
+
+impl TryFrom<SubscribeRequest> for NewSubscriber {
+    type Error = Vec<InvalidParam>;
+
+    /// Reports every invalid field, not only the first one.
+    fn try_from(request: SubscribeRequest) -> Result<Self, Self::Error> {
+        let mut invalid_params = Vec::new();
+        let mut check = |name: &'static str, reason: String| {
+            invalid_params.push(InvalidParam { name, reason });
+        };
+
+        let name = string(request.name)
+            .and_then(SubscriberName::parse)
+            .map_err(|e| check("name", e));
+        let email = string(request.email)
+            .and_then(SubscriberEmail::parse)
+            .map_err(|e| check("email", e));
+        let lists = match request.lists {
+            Value::Null => ListSlug::parse_many(None),
+            lists => strings(lists).and_then(ListSlug::parse_all),
+        }
+        .map_err(|e| check("lists", e));
+        let attributes = attributes(request.attributes)
+            .and_then(SubscriberAttributes::parse)
+            .map_err(|e| check("attributes", e));
+
+        match (name, email, lists, attributes) {
+            (Ok(name), Ok(email), Ok(lists), Ok(attributes)) => Ok(NewSubscriber {
+                name,
+                email,
+                lists,
+                attributes,
+            }),
+            _ => Err(invalid_params),
+        }
+    }
+}
+
+/// A string field, null standing for an empty one.
+fn string(value: Value) -> Result<String, String> {
+    match value {
+        Value::Null => Ok(String::new()),
+        Value::String(value) => Ok(value),
+        _ => Err(String::from("Must be a string")),
+    }
+}
+
+fn strings(value: Value) -> Result<Vec<String>, String> {
+    let error = || String::from("Must be an array of strings");
+    match value {
+        Value::Array(values) => values
+            .into_iter()
+            .map(|value| match value {
+                Value::String(value) => Ok(value),
+                _ => Err(error()),
+            })
+            .collect(),
+        _ => Err(error()),
+    }
+}
+
+fn attributes(value: Value) -> Result<HashMap<String, String>, String> {
+    match value {
+        Value::Null => Ok(HashMap::new()),
+        Value::Object(attributes) => attributes
+            .into_iter()
+            .map(|(key, value)| match value {
+                Value::String(value) => Ok((key, value)),
+                _ => Err(format!("The value of attribute {key} must be a string")),
+            })
+            .collect(),
+        _ => Err(String::from("Must be an object of strings")),
+    }
+}
+
+#[tracing::instrument(
+    name = "Adding a new subscriber through the API"
+    skip(request, db_pool, base_url),
+    fields(
+        subscriber_name = %request.name,
+        subscriber_email = %request.email,
+        subscriber_lists = ?request.lists,
+    )
+)]
+pub async fn post_api_subscription(
+    request: web::Json<SubscribeRequest>,
+    db_pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let new_subscriber: NewSubscriber = match request.0.try_into() {
+        Ok(new_subscriber) => new_subscriber,
+        Err(invalid_params) => return Problem::invalid_params(invalid_params).response(),
+    };
+
+    match subscribe(&db_pool, &base_url.0, &new_subscriber).await {
+        Ok(()) => HttpResponse::Accepted().json(serde_json::json!({
+            "status": "pending_confirmation"
+        })),
+        Err(SubscribeError::UnknownLists) => Problem::invalid_params(vec![InvalidParam {
+            name: "lists",
+            reason: String::from("Some of the requested lists do not exist"),
+        }])
+        .response(),
+        Err(SubscribeError::AlreadySubscribed) => Problem::new(
+            StatusCode::CONFLICT,
+            "The email is already subscribed to the requested lists",
+        )
+        .with_invalid_params(vec![InvalidParam {
+            name: "email",
+            reason: String::from("Already confirmed on every requested list"),
+        }])
+        .response(),
+        Err(SubscribeError::Unexpected) => Problem::internal_server_error().response(),
+    }
+}
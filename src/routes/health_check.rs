@@ -1,5 +1,129 @@
-use actix_web::{HttpResponse, Responder};
+use std::{future::Future, time::Instant};
 
+use actix_web::{web, HttpResponse, Responder};
+use sqlx::PgPool;
+
+use crate::{configuration::HealthSettings, email_client::EmailClient, startup::MIGRATOR};
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    Fail,
+}
+
+#[derive(serde::Serialize)]
+pub struct CheckReport {
+    name: &'static str,
+    status: CheckStatus,
+    latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ReadinessReport {
+    status: CheckStatus,
+    checks: Vec<CheckReport>,
+}
+
+/// Liveness: the process is up and serving requests.
 pub async fn get_health() -> impl Responder {
     HttpResponse::Ok()
 }
+
+/// Readiness: the dependencies needed to serve traffic are available.
+#[tracing::instrument(name = "Readiness check", skip(db_pool, email_client, settings))]
+pub async fn get_readiness(
+    db_pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    settings: web::Data<HealthSettings>,
+) -> HttpResponse {
+    let timeout = settings.timeout();
+    let (database, migrations) = tokio::join!(
+        run_check("database", timeout, ping_database(&db_pool)),
+        run_check("migrations", timeout, check_migrations(&db_pool)),
+    );
+    let mut checks = vec![database, migrations];
+    if settings.check_email_api {
+        checks.push(
+            run_check("email_api", timeout, async {
+                email_client.probe().await.map_err(|e| e.to_string())
+            })
+            .await,
+        );
+    }
+
+    let status = if checks.iter().all(|c| c.status == CheckStatus::Ok) {
+        CheckStatus::Ok
+    } else {
+        CheckStatus::Fail
+    };
+    let report = ReadinessReport { status, checks };
+
+    match status {
+        CheckStatus::Ok => HttpResponse::Ok().json(report),
+        CheckStatus::Fail => HttpResponse::ServiceUnavailable().json(report),
+    }
+}
+
+async fn run_check(
+    name: &'static str,
+    timeout: std::time::Duration,
+    check: impl Future<Output = Result<(), String>>,
+) -> CheckReport {
+    let start = Instant::now();
+    let result = match tokio::time::timeout(timeout, check).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("Timed out after {}ms", timeout.as_millis())),
+    };
+    let latency_ms = start.elapsed().as_millis() as u64;
+
+    match result {
+        Ok(()) => CheckReport {
+            name,
+            status: CheckStatus::Ok,
+            latency_ms,
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Readiness check {} failed: {}", name, e);
+            CheckReport {
+                name,
+                status: CheckStatus::Fail,
+                latency_ms,
+                error: Some(e),
+            }
+        }
+    }
+}
+
+async fn ping_database(pool: &PgPool) -> Result<(), String> {
+    sqlx::query("SELECT 1")
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Checks every migration this build embeds was applied. Migrations added by
+/// a newer build, rolling out alongside this one, are fine.
+async fn check_migrations(pool: &PgPool) -> Result<(), String> {
+    let applied: Vec<i64> =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+    let missing: Vec<String> = MIGRATOR
+        .iter()
+        .map(|m| m.version)
+        .filter(|version| !applied.contains(version))
+        .map(|version| version.to_string())
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Missing migrations {}", missing.join(", ")))
+    }
+}
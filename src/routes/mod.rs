@@ -1,7 +1,15 @@
+mod admin;
+mod api;
 mod health_check;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_email;
+mod subscriptions_preferences;
 
+pub use admin::*;
+pub use api::*;
 pub use health_check::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_email::*;
+pub use subscriptions_preferences::*;
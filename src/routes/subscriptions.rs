@@ -1,19 +1,30 @@
 use std::char;
+use std::collections::HashMap;
 
 use actix_web::{web, HttpResponse};
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
-use sqlx::{types::chrono::Utc, types::uuid::Uuid};
+use sqlx::{types::chrono::Utc, types::uuid::Uuid, types::Json};
 use sqlx::{Executor, PgPool, Postgres, Transaction};
 
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
-use crate::email_client::EmailClient;
+use crate::db_pools::begin_transaction;
+use crate::domain::{
+    ListSlug, NewSubscriber, SubscriberAttributes, SubscriberEmail, SubscriberName,
+};
+use crate::email_outbox::enqueue_email;
+use crate::metrics::SUBSCRIPTIONS_CREATED_TOTAL;
+use crate::routes::{preferences_footer_html, preferences_footer_text};
 use crate::startup::ApplicationBaseUrl;
 
 #[derive(serde::Deserialize, Debug)]
 pub struct SubscribeFormData {
     name: String,
     email: String,
+    /// Comma separated slugs of the lists to join, defaults to the main newsletter.
+    lists: Option<String>,
+    /// Hidden `attr_<name>` fields become custom attributes of the subscriber.
+    #[serde(flatten)]
+    extra: HashMap<String, String>,
 }
 
 impl TryFrom<SubscribeFormData> for NewSubscriber {
@@ -22,81 +33,181 @@ impl TryFrom<SubscribeFormData> for NewSubscriber {
     fn try_from(form: SubscribeFormData) -> Result<Self, Self::Error> {
         let name = SubscriberName::parse(form.name)?;
         let email = SubscriberEmail::parse(form.email)?;
-        Ok(NewSubscriber { name, email })
+        let lists = ListSlug::parse_many(form.lists)?;
+        let attributes = SubscriberAttributes::parse(
+            form.extra
+                .into_iter()
+                .filter_map(|(k, v)| Some((k.strip_prefix("attr_")?.to_string(), v)))
+                .collect(),
+        )?;
+        Ok(NewSubscriber {
+            name,
+            email,
+            lists,
+            attributes,
+        })
     }
 }
 
 #[tracing::instrument(
     name = "Adding a new subscriber"
-    skip(form, db_pool, email_client, base_url),
+    skip(form, db_pool, base_url),
     fields(
         subscriber_name = %form.name,
         subscriber_email = %form.email,
+        subscriber_lists = ?form.lists,
     )
 )]
 pub async fn post_subscribe(
     form: web::Form<SubscribeFormData>,
     db_pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
 ) -> HttpResponse {
-    let new_subscriber = match form.0.try_into() {
+    let new_subscriber: NewSubscriber = match form.0.try_into() {
         Ok(name) => name,
         Err(_) => return HttpResponse::BadRequest().finish(),
     };
 
-    let mut transaction = match db_pool.begin().await {
-        Ok(txn) => txn,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
+    match subscribe(&db_pool, &base_url.0, &new_subscriber).await {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(SubscribeError::UnknownLists) => HttpResponse::BadRequest().finish(),
+        Err(SubscribeError::AlreadySubscribed) => HttpResponse::Conflict().finish(),
+        Err(SubscribeError::Unexpected) => HttpResponse::InternalServerError().finish(),
+    }
+}
 
-    let subscriber_id = match insert_subscriber(&mut transaction, &new_subscriber).await {
-        Ok(subscriber_id) => subscriber_id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
+pub enum SubscribeError {
+    /// Some of the requested lists do not exist.
+    UnknownLists,
+    /// The email is already confirmed on every requested list.
+    AlreadySubscribed,
+    Unexpected,
+}
+
+/// Stores `new_subscriber`, pending confirmation of their subscription to
+/// the requested lists, and queues the confirmation email.
+///
+/// A known email keeps its stored details: the requested lists it is not
+/// confirmed on yet are (re)added as pending under a fresh confirmation
+/// link, so signing up again also replaces an expired link.
+pub async fn subscribe(
+    db_pool: &PgPool,
+    base_url: &str,
+    new_subscriber: &NewSubscriber,
+) -> Result<(), SubscribeError> {
+    let list_ids = match get_list_ids(db_pool, &new_subscriber.lists).await {
+        Ok(list_ids) if list_ids.len() == new_subscriber.lists.len() => list_ids,
+        Ok(_) => return Err(SubscribeError::UnknownLists),
+        Err(_) => return Err(SubscribeError::Unexpected),
     };
 
-    let subscription_token = generate_subscription_token();
+    let mut transaction = begin_transaction(db_pool)
+        .await
+        .map_err(|_| SubscribeError::Unexpected)?;
 
-    if store_token(&mut transaction, subscriber_id, &subscription_token)
+    let subscriber = insert_subscriber(&mut transaction, new_subscriber)
         .await
-        .is_err()
-    {
-        return HttpResponse::InternalServerError().finish();
+        .map_err(|_| SubscribeError::Unexpected)?;
+
+    let subscription_token = generate_subscription_token();
+
+    store_token(
+        &mut transaction,
+        subscriber.id,
+        &subscription_token,
+        &TokenPurpose::ConfirmSubscription,
+    )
+    .await
+    .map_err(|_| SubscribeError::Unexpected)?;
+
+    let pending_lists = insert_list_subscriptions(
+        &mut transaction,
+        subscriber.id,
+        &list_ids,
+        &subscription_token,
+    )
+    .await
+    .map_err(|_| SubscribeError::Unexpected)?;
+    if pending_lists == 0 {
+        return Err(SubscribeError::AlreadySubscribed);
+    }
+    if !subscriber.is_new {
+        delete_superseded_tokens(&mut transaction, subscriber.id)
+            .await
+            .map_err(|_| SubscribeError::Unexpected)?;
     }
 
-    if send_confirmation_email(
-        &email_client,
+    queue_confirmation_email(
+        &mut transaction,
         new_subscriber,
-        &base_url.0,
+        base_url,
         &subscription_token,
+        &subscriber.preferences_token,
     )
     .await
-    .is_err()
-    {
-        return HttpResponse::InternalServerError().finish();
+    .map_err(|_| SubscribeError::Unexpected)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|_| SubscribeError::Unexpected)?;
+    if subscriber.is_new {
+        SUBSCRIPTIONS_CREATED_TOTAL.inc();
     }
 
-    if transaction.commit().await.is_err() {
-        return HttpResponse::InternalServerError().finish();
+    Ok(())
+}
+
+/// The subscriber a signup applies to.
+pub struct StoredSubscriber {
+    pub id: Uuid,
+    pub preferences_token: String,
+    /// Whether the signup created it, rather than found its email.
+    pub is_new: bool,
+}
+
+/// What following the link carrying a subscription token does.
+#[derive(Debug)]
+pub enum TokenPurpose {
+    ConfirmSubscription,
+    ChangeEmail(SubscriberEmail),
+}
+
+impl TokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ConfirmSubscription => "confirm_subscription",
+            Self::ChangeEmail(_) => "change_email",
+        }
     }
 
-    HttpResponse::Ok().finish()
+    fn new_email(&self) -> Option<&str> {
+        match self {
+            Self::ConfirmSubscription => None,
+            Self::ChangeEmail(email) => Some(email.as_ref()),
+        }
+    }
 }
 
 #[tracing::instrument(
     name = "Storing email confirmation token in DB",
-    skip(subscription_token, transaction)
+    skip(subscription_token, transaction, purpose),
+    fields(purpose = purpose.as_str())
 )]
 pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
     subscription_token: &str,
+    purpose: &TokenPurpose,
 ) -> Result<(), sqlx::Error> {
     let query = sqlx::query!(
-        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-        VALUES ($1, $2)"#,
+        r#"INSERT INTO subscription_tokens (subscription_token, subscriber_id, purpose, new_email, created_at)
+        VALUES ($1, $2, $3, $4, $5)"#,
         subscription_token,
         subscriber_id,
+        purpose.as_str(),
+        purpose.new_email(),
+        Utc::now(),
     );
 
     transaction.execute(query).await.map_err(|e| {
@@ -107,35 +218,71 @@ pub async fn store_token(
     Ok(())
 }
 
+/// Deletes the confirmation tokens no list is pending or confirmed on any
+/// longer, once a new signup took their pending lists over: their links
+/// would otherwise confirm the subscriber without any of them.
+#[tracing::instrument(name = "Deleting superseded confirmation tokens", skip(transaction))]
+pub async fn delete_superseded_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+DELETE FROM subscription_tokens t
+WHERE t.subscriber_id = $1 AND t.purpose = 'confirm_subscription'
+AND NOT EXISTS (
+    SELECT 1 FROM list_subscriptions l WHERE l.subscription_token = t.subscription_token
+)
+"#,
+        subscriber_id,
+    );
+    transaction.execute(query).await.map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(())
+}
+
+/// Queues the confirmation link in the outbox, to be sent once the new
+/// subscriber is committed.
 #[tracing::instrument(
-    name = "Sending confirmation link to new subscriber",
-    skip(email_client, new_subscriber, base_url, token)
+    name = "Queueing confirmation link for new subscriber",
+    skip(transaction, new_subscriber, base_url, token, preferences_token)
 )]
-pub async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+pub async fn queue_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
     base_url: &str,
     token: &str,
-) -> Result<(), reqwest::Error> {
+    preferences_token: &str,
+) -> Result<(), sqlx::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, token
     );
     let html_part = &format!(
         "<h1>Welcome to our newsletter!! <br />\
-                Click <a href=\"{}\">here</a> to confirm your subscription",
-        confirmation_link
+                Click <a href=\"{}\">here</a> to confirm your subscription{}",
+        confirmation_link,
+        preferences_footer_html(base_url, preferences_token)
     );
     let text_part = &format!(
-        "Welcome to our newsletter!! Visit {} to confirm your subscription",
-        confirmation_link
+        "Welcome to our newsletter!! Visit {} to confirm your subscription{}",
+        confirmation_link,
+        preferences_footer_text(base_url, preferences_token)
     );
-    email_client
-        .send_email(new_subscriber.email, "Welcome!", html_part, text_part)
-        .await
+    enqueue_email(
+        transaction,
+        &new_subscriber.email,
+        "Welcome!",
+        Some(html_part),
+        text_part,
+    )
+    .await
 }
 
-fn generate_subscription_token() -> String {
+pub(crate) fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -150,22 +297,106 @@ fn generate_subscription_token() -> String {
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
+) -> Result<StoredSubscriber, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
+    let preferences_token = generate_subscription_token();
     let query = sqlx::query!(
         r#"
-INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-VALUES ($1, $2, $3, $4, 'pending_confirmation')
+INSERT INTO subscriptions (id, email, name, subscribed_at, status, preferences_token, attributes)
+VALUES ($1, $2, $3, $4, 'pending_confirmation', $5, $6)
+ON CONFLICT (email) DO NOTHING
 "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        preferences_token,
+        Json(&new_subscriber.attributes) as _,
     );
-    transaction.execute(query).await.map_err(|e| {
+    let inserted = transaction
+        .execute(query)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to exec query: {:?}", e);
+            e
+        })?
+        .rows_affected();
+    if inserted == 1 {
+        return Ok(StoredSubscriber {
+            id: subscriber_id,
+            preferences_token,
+            is_new: true,
+        });
+    }
+
+    // Signups of a known email leave its details alone, they only request lists
+    sqlx::query_as!(
+        StoredSubscriber,
+        r#"
+SELECT id, preferences_token, false AS "is_new!"
+FROM subscriptions
+WHERE email = $1
+FOR UPDATE
+"#,
+        new_subscriber.email.as_ref(),
+    )
+    .fetch_one(&mut **transaction)
+    .await
+    .map_err(|e| {
         tracing::error!("Failed to exec query: {:?}", e);
         e
-    })?;
+    })
+}
+
+#[tracing::instrument(name = "Resolving requested lists", skip(pool))]
+pub async fn get_list_ids(pool: &PgPool, lists: &[ListSlug]) -> Result<Vec<Uuid>, sqlx::Error> {
+    let slugs: Vec<String> = lists.iter().map(|l| l.as_ref().to_string()).collect();
+    let rows = sqlx::query!(r#"SELECT id FROM lists WHERE slug = ANY($1)"#, &slugs)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+
+    Ok(rows.into_iter().map(|r| r.id).collect())
+}
+
+/// Marks the requested lists as pending confirmation through
+/// `subscription_token`, except those the subscriber is already confirmed
+/// on. Returns how many lists are now pending on it.
+#[tracing::instrument(
+    name = "Saving requested lists of subscriber in DB",
+    skip(transaction, subscription_token)
+)]
+pub async fn insert_list_subscriptions(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_ids: &[Uuid],
+    subscription_token: &str,
+) -> Result<u64, sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscription_token, subscribed_at)
+SELECT list_id, $2, 'pending_confirmation', $3, $4
+FROM UNNEST($1::uuid[]) AS list_id
+ON CONFLICT (list_id, subscriber_id) DO UPDATE
+SET status = EXCLUDED.status, subscription_token = EXCLUDED.subscription_token
+WHERE list_subscriptions.status <> 'confirmed'
+"#,
+        list_ids,
+        subscriber_id,
+        subscription_token,
+        Utc::now()
+    );
+    let pending_lists = transaction
+        .execute(query)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to exec query: {:?}", e);
+            e
+        })?
+        .rows_affected();
 
-    Ok(subscriber_id)
+    Ok(pending_lists)
 }
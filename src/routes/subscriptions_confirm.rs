@@ -1,42 +1,204 @@
-use actix_web::{web, HttpResponse};
-use sqlx::PgPool;
+use actix_web::{
+    http::{header, header::ContentType, StatusCode},
+    web, HttpResponse,
+};
+use sqlx::{
+    types::chrono::{DateTime, Utc},
+    Executor, PgPool, Postgres, Transaction,
+};
 use uuid::Uuid;
 
+use crate::configuration::ConfirmationSettings;
+use crate::db_pools::begin_transaction;
+use crate::metrics::CONFIRMATIONS_COMPLETED_TOTAL;
+use crate::routes::html_escape;
+
 #[derive(serde::Deserialize, Debug)]
 pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, db_pool))]
+/// What following a confirmation link did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfirmationOutcome {
+    Confirmed,
+    AlreadyConfirmed,
+    InvalidToken,
+    ExpiredToken,
+}
+
+impl ConfirmationOutcome {
+    /// The `status` query parameter passed to the redirect URL.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Confirmed => "confirmed",
+            Self::AlreadyConfirmed => "already_confirmed",
+            Self::InvalidToken => "invalid_token",
+            Self::ExpiredToken => "expired_token",
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Confirmed | Self::AlreadyConfirmed => StatusCode::OK,
+            Self::InvalidToken => StatusCode::UNAUTHORIZED,
+            Self::ExpiredToken => StatusCode::GONE,
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            Self::Confirmed => "Subscription confirmed",
+            Self::AlreadyConfirmed => "Already confirmed",
+            Self::InvalidToken => "Invalid confirmation link",
+            Self::ExpiredToken => "Confirmation link expired",
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            Self::Confirmed => {
+                "Thanks for confirming your subscription, our next issue is on its way."
+            }
+            Self::AlreadyConfirmed => {
+                "Your subscription was already confirmed, there is nothing left to do."
+            }
+            Self::InvalidToken => {
+                "This confirmation link is not valid. \
+                Make sure you copied the whole link from the email."
+            }
+            Self::ExpiredToken => {
+                "This confirmation link has expired. \
+                Please subscribe again to receive a new one."
+            }
+        }
+    }
+}
+
+struct ConfirmationToken {
+    subscriber_id: Uuid,
+    subscriber_status: String,
+    /// Lists still waiting for this token to be confirmed.
+    pending_lists: i64,
+    created_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(parameters, db_pool, settings)
+)]
 pub async fn subscription_confirm(
     parameters: web::Query<Parameters>,
     db_pool: web::Data<PgPool>,
+    settings: web::Data<ConfirmationSettings>,
 ) -> HttpResponse {
-    let subscriber_id =
-        match get_subscriber_id_from_token(&db_pool, &parameters.subscription_token).await {
-            Ok(subscriber_id) => subscriber_id,
-            Err(_) => return HttpResponse::InternalServerError().finish(),
-        };
-
-    match subscriber_id {
-        Some(id) => {
-            if confirm_subscriber(&db_pool, id).await.is_err() {
+    let token = match get_confirmation_token(&db_pool, &parameters.subscription_token).await {
+        Ok(token) => token,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let outcome = match token {
+        None => ConfirmationOutcome::InvalidToken,
+        Some(token) if token.subscriber_status == "confirmed" && token.pending_lists == 0 => {
+            ConfirmationOutcome::AlreadyConfirmed
+        }
+        Some(token) if is_expired(token.created_at, &settings) => ConfirmationOutcome::ExpiredToken,
+        Some(token) => {
+            let mut transaction = match begin_transaction(&db_pool).await {
+                Ok(txn) => txn,
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            };
+
+            if confirm_subscriber(&mut transaction, token.subscriber_id)
+                .await
+                .is_err()
+            {
+                return HttpResponse::InternalServerError().finish();
+            }
+
+            if confirm_list_subscriptions(&mut transaction, &parameters.subscription_token)
+                .await
+                .is_err()
+            {
                 return HttpResponse::InternalServerError().finish();
             }
-            HttpResponse::Ok().finish()
+
+            if transaction.commit().await.is_err() {
+                return HttpResponse::InternalServerError().finish();
+            }
+            CONFIRMATIONS_COMPLETED_TOTAL.inc();
+            ConfirmationOutcome::Confirmed
         }
-        None => HttpResponse::Unauthorized().finish(),
+    };
+    tracing::info!(outcome = outcome.as_str(), "Confirmation link followed");
+
+    match &settings.redirect_url {
+        Some(redirect_url) => redirect(redirect_url, outcome),
+        None => HttpResponse::build(outcome.status_code())
+            .content_type(ContentType::html())
+            .body(confirmation_page(outcome.title(), outcome.message())),
     }
 }
 
-#[tracing::instrument(name = "Get subscriber_id from token", skip())]
-pub async fn get_subscriber_id_from_token(
+pub(crate) fn is_expired(created_at: DateTime<Utc>, settings: &ConfirmationSettings) -> bool {
+    // A token from the future, with clocks out of sync, is not expired
+    (Utc::now() - created_at)
+        .to_std()
+        .is_ok_and(|age| age > settings.token_ttl())
+}
+
+/// Sends the browser to `redirect_url`, telling the outcome in a `status`
+/// query parameter.
+fn redirect(redirect_url: &str, outcome: ConfirmationOutcome) -> HttpResponse {
+    let mut location = match reqwest::Url::parse(redirect_url) {
+        Ok(location) => location,
+        Err(e) => {
+            tracing::error!("Invalid confirmation redirect URL: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    location
+        .query_pairs_mut()
+        .append_pair("status", outcome.as_str());
+
+    HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, location.as_str()))
+        .finish()
+}
+
+/// A page telling what following a link from an email did.
+pub(crate) fn confirmation_page(title: &str, message: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    <p>{message}</p>
+</body>
+</html>"#,
+        title = html_escape(title),
+        message = html_escape(message),
+    )
+}
+
+#[tracing::instrument(name = "Get confirmation token", skip(pool, subscription_token))]
+async fn get_confirmation_token(
     pool: &PgPool,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        r#"SELECT subscriber_id FROM subscription_tokens
-        WHERE subscription_token = $1
+) -> Result<Option<ConfirmationToken>, sqlx::Error> {
+    sqlx::query_as!(
+        ConfirmationToken,
+        r#"SELECT t.subscriber_id, s.status AS subscriber_status, t.created_at,
+            (SELECT COUNT(*) FROM list_subscriptions l
+            WHERE l.subscription_token = t.subscription_token
+            AND l.status = 'pending_confirmation') AS "pending_lists!"
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE t.subscription_token = $1 AND t.purpose = 'confirm_subscription'
         "#,
         subscription_token
     )
@@ -45,21 +207,43 @@ pub async fn get_subscriber_id_from_token(
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
-    })?;
-
-    Ok(result.map(|r| r.subscriber_id))
+    })
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip())]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+#[tracing::instrument(name = "Mark subscriber as confirmed", skip(transaction))]
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
         r#"UPDATE subscriptions SET status = 'confirmed'
         WHERE id = $1"#,
         subscriber_id,
-    )
-    .execute(pool)
-    .await
-    .map_err(|e| {
+    );
+
+    transaction.execute(query).await.map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Mark lists requested with a confirmation link as confirmed",
+    skip(transaction, subscription_token)
+)]
+pub async fn confirm_list_subscriptions(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"UPDATE list_subscriptions SET status = 'confirmed'
+        WHERE subscription_token = $1 AND status = 'pending_confirmation'"#,
+        subscription_token,
+    );
+
+    transaction.execute(query).await.map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
     })?;
@@ -0,0 +1,293 @@
+use actix_web::{
+    http::{header::ContentType, StatusCode},
+    web, HttpResponse,
+};
+use sqlx::{
+    types::chrono::{DateTime, Utc},
+    Executor, PgPool, Postgres, Transaction,
+};
+use uuid::Uuid;
+
+use crate::configuration::ConfirmationSettings;
+use crate::db_pools::begin_transaction;
+use crate::domain::SubscriberEmail;
+use crate::email_outbox::enqueue_email;
+use crate::routes::{
+    confirmation_page, generate_subscription_token, get_subscriber_preferences, is_expired,
+    record_change, render_preferences, store_token, TokenPurpose,
+};
+use crate::startup::ApplicationBaseUrl;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ChangeEmailFormData {
+    token: String,
+    email: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ConfirmEmailChangeParameters {
+    subscription_token: String,
+}
+
+/// What following an email change link did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EmailChangeOutcome {
+    Changed,
+    InvalidToken,
+    ExpiredToken,
+    AddressTaken,
+}
+
+impl EmailChangeOutcome {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Changed => StatusCode::OK,
+            Self::InvalidToken => StatusCode::UNAUTHORIZED,
+            Self::ExpiredToken => StatusCode::GONE,
+            Self::AddressTaken => StatusCode::CONFLICT,
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            Self::Changed => "Email address changed",
+            Self::InvalidToken => "Invalid confirmation link",
+            Self::ExpiredToken => "Confirmation link expired",
+            Self::AddressTaken => "Email address already subscribed",
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            Self::Changed => "Our next issue will be sent to your new email address.",
+            Self::InvalidToken => {
+                "This confirmation link is not valid. \
+                Make sure you copied the whole link from the email."
+            }
+            Self::ExpiredToken => {
+                "This confirmation link has expired. \
+                Please ask to change your email address again."
+            }
+            Self::AddressTaken => {
+                "This email address is already subscribed, \
+                your subscription keeps using the previous one."
+            }
+        }
+    }
+
+    fn page(self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .content_type(ContentType::html())
+            .body(confirmation_page(self.title(), self.message()))
+    }
+}
+
+struct PendingEmailChange {
+    subscriber_id: Uuid,
+    old_email: String,
+    new_email: String,
+    created_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "Request a change of email address",
+    skip(form, db_pool, base_url)
+)]
+pub async fn post_change_email(
+    form: web::Form<ChangeEmailFormData>,
+    db_pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    let subscriber = match get_subscriber_preferences(&db_pool, &form.token).await {
+        Ok(Some(subscriber)) => subscriber,
+        Ok(None) => return HttpResponse::Unauthorized().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let new_email = match SubscriberEmail::parse(form.email.clone()) {
+        Ok(email) if email.as_ref() != subscriber.email => email,
+        _ => return HttpResponse::BadRequest().finish(),
+    };
+
+    // Whether the address is already subscribed is only checked once the link
+    // is followed, so this page does not tell who subscribed
+    let mut transaction = match begin_transaction(&db_pool).await {
+        Ok(txn) => txn,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let subscription_token = generate_subscription_token();
+
+    if store_token(
+        &mut transaction,
+        subscriber.id,
+        &subscription_token,
+        &TokenPurpose::ChangeEmail(new_email.clone()),
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    if queue_email_change_confirmation(
+        &mut transaction,
+        &new_email,
+        &base_url.0,
+        &subscription_token,
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    render_preferences(
+        &db_pool,
+        &form.token,
+        &subscriber,
+        Some("We sent a confirmation link to your new email address."),
+    )
+    .await
+}
+
+#[tracing::instrument(
+    name = "Confirm a change of email address",
+    skip(parameters, db_pool, settings)
+)]
+pub async fn confirm_email_change(
+    parameters: web::Query<ConfirmEmailChangeParameters>,
+    db_pool: web::Data<PgPool>,
+    settings: web::Data<ConfirmationSettings>,
+) -> HttpResponse {
+    let mut transaction = match begin_transaction(&db_pool).await {
+        Ok(txn) => txn,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let change =
+        match take_pending_email_change(&mut transaction, &parameters.subscription_token).await {
+            Ok(Some(change)) => change,
+            Ok(None) => return EmailChangeOutcome::InvalidToken.page(),
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
+    // Dropping the transaction keeps the token, so the link keeps saying it expired
+    if is_expired(change.created_at, &settings) {
+        return EmailChangeOutcome::ExpiredToken.page();
+    }
+
+    match update_email(&mut transaction, &change).await {
+        Ok(()) => {}
+        // The address was claimed by another subscriber after the change was requested
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return EmailChangeOutcome::AddressTaken.page()
+        }
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    }
+
+    if record_change(
+        &mut transaction,
+        change.subscriber_id,
+        "email",
+        &change.old_email,
+        &change.new_email,
+    )
+    .await
+    .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    EmailChangeOutcome::Changed.page()
+}
+
+#[tracing::instrument(
+    name = "Queueing email change confirmation link",
+    skip(transaction, new_email, base_url, token)
+)]
+pub async fn queue_email_change_confirmation(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_email: &SubscriberEmail,
+    base_url: &str,
+    token: &str,
+) -> Result<(), sqlx::Error> {
+    let confirmation_link = format!(
+        "{}/subscriptions/email/confirm?subscription_token={}",
+        base_url, token
+    );
+    let html_part = &format!(
+        "You asked to receive our newsletter at this address. <br />\
+                Click <a href=\"{}\">here</a> to confirm the change",
+        confirmation_link
+    );
+    let text_part = &format!(
+        "You asked to receive our newsletter at this address. Visit {} to confirm the change",
+        confirmation_link
+    );
+    enqueue_email(
+        transaction,
+        new_email,
+        "Confirm your new email address",
+        Some(html_part),
+        text_part,
+    )
+    .await
+}
+
+#[tracing::instrument(
+    name = "Consume email change token",
+    skip(transaction, subscription_token)
+)]
+async fn take_pending_email_change(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<Option<PendingEmailChange>, sqlx::Error> {
+    sqlx::query_as!(
+        PendingEmailChange,
+        r#"
+WITH consumed AS (
+    DELETE FROM subscription_tokens
+    WHERE subscription_token = $1 AND purpose = 'change_email'
+    RETURNING subscriber_id, new_email, created_at
+)
+SELECT consumed.subscriber_id, subscriptions.email AS old_email,
+    consumed.new_email AS "new_email!", consumed.created_at
+FROM consumed
+JOIN subscriptions ON subscriptions.id = consumed.subscriber_id
+"#,
+        subscription_token,
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[tracing::instrument(name = "Swap subscriber email", skip(transaction, change))]
+async fn update_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    change: &PendingEmailChange,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"UPDATE subscriptions SET email = $1 WHERE id = $2"#,
+        change.new_email,
+        change.subscriber_id,
+    );
+
+    transaction.execute(query).await.map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(())
+}
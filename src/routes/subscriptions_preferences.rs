@@ -0,0 +1,526 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use sqlx::{types::chrono::Utc, Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::db_pools::begin_transaction;
+use crate::domain::{EmailFormat, ListSlug, SubscriberName};
+use crate::routes::get_list_ids;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct PreferencesParameters {
+    token: String,
+}
+
+/// Fields submitted by the preference center form. Topics are checkboxes, so
+/// `lists` can be repeated.
+#[derive(Debug)]
+pub struct PreferencesFormData {
+    token: String,
+    name: String,
+    format: String,
+    lists: Vec<String>,
+}
+
+impl TryFrom<Vec<(String, String)>> for PreferencesFormData {
+    type Error = String;
+
+    fn try_from(fields: Vec<(String, String)>) -> Result<Self, Self::Error> {
+        let (mut token, mut name, mut format) = (None, None, None);
+        let mut lists = Vec::new();
+        for (key, value) in fields {
+            match key.as_str() {
+                "token" => token = Some(value),
+                "name" => name = Some(value),
+                "format" => format = Some(value),
+                "lists" => lists.push(value),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            token: token.ok_or("token field is missing")?,
+            name: name.ok_or("name field is missing")?,
+            format: format.ok_or("format field is missing")?,
+            lists,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct UpdatedPreferences {
+    name: SubscriberName,
+    format: EmailFormat,
+    lists: Vec<ListSlug>,
+}
+
+impl TryFrom<&PreferencesFormData> for UpdatedPreferences {
+    type Error = String;
+
+    fn try_from(form: &PreferencesFormData) -> Result<Self, Self::Error> {
+        let name = SubscriberName::parse(form.name.clone())?;
+        let format = EmailFormat::try_from(form.format.clone())?;
+        let mut lists = Vec::new();
+        for slug in &form.lists {
+            let slug = ListSlug::parse(slug.clone())?;
+            if !lists.contains(&slug) {
+                lists.push(slug);
+            }
+        }
+        Ok(Self {
+            name,
+            format,
+            lists,
+        })
+    }
+}
+
+pub(crate) struct SubscriberPreferences {
+    pub(crate) id: Uuid,
+    pub(crate) email: String,
+    name: String,
+    status: String,
+    email_format: String,
+}
+
+struct ListChoice {
+    slug: String,
+    name: String,
+    subscribed: bool,
+}
+
+pub fn preferences_link(base_url: &str, preferences_token: &str) -> String {
+    format!(
+        "{}/subscriptions/preferences?token={}",
+        base_url, preferences_token
+    )
+}
+
+pub fn preferences_footer_html(base_url: &str, preferences_token: &str) -> String {
+    format!(
+        "<p><small><a href=\"{}\">Manage your subscription preferences</a></small></p>",
+        preferences_link(base_url, preferences_token)
+    )
+}
+
+pub fn preferences_footer_text(base_url: &str, preferences_token: &str) -> String {
+    format!(
+        "\n\nManage your subscription preferences: {}",
+        preferences_link(base_url, preferences_token)
+    )
+}
+
+#[tracing::instrument(name = "Show subscriber preferences", skip(parameters, db_pool))]
+pub async fn get_preferences(
+    parameters: web::Query<PreferencesParameters>,
+    db_pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let subscriber = match get_subscriber_preferences(&db_pool, &parameters.token).await {
+        Ok(Some(subscriber)) => subscriber,
+        Ok(None) => return HttpResponse::Unauthorized().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    render_preferences(&db_pool, &parameters.token, &subscriber, None).await
+}
+
+#[tracing::instrument(name = "Update subscriber preferences", skip(form, db_pool))]
+pub async fn post_preferences(
+    form: web::Form<Vec<(String, String)>>,
+    db_pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let form: PreferencesFormData = match form.into_inner().try_into() {
+        Ok(form) => form,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+
+    let subscriber = match get_subscriber_preferences(&db_pool, &form.token).await {
+        Ok(Some(subscriber)) => subscriber,
+        Ok(None) => return HttpResponse::Unauthorized().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let preferences: UpdatedPreferences = match (&form).try_into() {
+        Ok(preferences) => preferences,
+        Err(_) => return HttpResponse::BadRequest().finish(),
+    };
+
+    let list_ids = match get_list_ids(&db_pool, &preferences.lists).await {
+        Ok(list_ids) if list_ids.len() == preferences.lists.len() => list_ids,
+        Ok(_) => return HttpResponse::BadRequest().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let mut transaction = match begin_transaction(&db_pool).await {
+        Ok(txn) => txn,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    if update_preferences(&mut transaction, &subscriber, &preferences, &list_ids)
+        .await
+        .is_err()
+    {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    if transaction.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    let subscriber = SubscriberPreferences {
+        name: preferences.name.as_ref().to_string(),
+        email_format: preferences.format.as_str().to_string(),
+        ..subscriber
+    };
+    render_preferences(
+        &db_pool,
+        &form.token,
+        &subscriber,
+        Some("Your preferences have been saved."),
+    )
+    .await
+}
+
+pub(crate) async fn render_preferences(
+    pool: &PgPool,
+    token: &str,
+    subscriber: &SubscriberPreferences,
+    notice: Option<&str>,
+) -> HttpResponse {
+    let lists = match get_list_choices(pool, subscriber.id).await {
+        Ok(lists) => lists,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(preferences_page(token, subscriber, &lists, notice))
+}
+
+fn preferences_page(
+    token: &str,
+    subscriber: &SubscriberPreferences,
+    lists: &[ListChoice],
+    notice: Option<&str>,
+) -> String {
+    let notice = notice
+        .map(|n| format!("<p><i>{}</i></p>", html_escape(n)))
+        .unwrap_or_default();
+    let topics: String = lists
+        .iter()
+        .map(|list| {
+            format!(
+                r#"<label><input type="checkbox" name="lists" value="{}"{}> {}</label><br>"#,
+                html_escape(&list.slug),
+                if list.subscribed { " checked" } else { "" },
+                html_escape(&list.name)
+            )
+        })
+        .collect();
+    let checked = |format: EmailFormat| {
+        if subscriber.email_format == format.as_str() {
+            " checked"
+        } else {
+            ""
+        }
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscription preferences</title>
+</head>
+<body>
+    <h1>Subscription preferences</h1>
+    {notice}
+    <form action="/subscriptions/preferences" method="post">
+        <input type="hidden" name="token" value="{token}">
+        <label>Display name
+            <input type="text" name="name" value="{name}">
+        </label>
+        <fieldset>
+            <legend>Topics</legend>
+            {topics}
+        </fieldset>
+        <fieldset>
+            <legend>Format</legend>
+            <label><input type="radio" name="format" value="html"{html}> HTML</label>
+            <label><input type="radio" name="format" value="text"{text}> Plain text</label>
+        </fieldset>
+        <button type="submit">Save preferences</button>
+    </form>
+    <form action="/subscriptions/email" method="post">
+        <input type="hidden" name="token" value="{token}">
+        <label>Email address
+            <input type="email" name="email" value="{email}">
+        </label>
+        <button type="submit">Change email address</button>
+    </form>
+</body>
+</html>"#,
+        token = html_escape(token),
+        name = html_escape(&subscriber.name),
+        email = html_escape(&subscriber.email),
+        html = checked(EmailFormat::Html),
+        text = checked(EmailFormat::Text),
+    )
+}
+
+pub(crate) fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#x27;")
+}
+
+#[tracing::instrument(name = "Get subscriber from preferences token", skip(pool, token))]
+pub(crate) async fn get_subscriber_preferences(
+    pool: &PgPool,
+    token: &str,
+) -> Result<Option<SubscriberPreferences>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriberPreferences,
+        r#"SELECT id, email, name, status, email_format FROM subscriptions
+        WHERE preferences_token = $1"#,
+        token,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[tracing::instrument(name = "Get list choices of subscriber", skip(pool))]
+async fn get_list_choices(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<ListChoice>, sqlx::Error> {
+    sqlx::query_as!(
+        ListChoice,
+        r#"SELECT lists.slug, lists.name,
+            COALESCE(list_subscriptions.status <> 'unsubscribed', false) AS "subscribed!"
+        FROM lists
+        LEFT JOIN list_subscriptions
+            ON list_subscriptions.list_id = lists.id
+            AND list_subscriptions.subscriber_id = $1
+        ORDER BY lists.slug"#,
+        subscriber_id,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })
+}
+
+#[tracing::instrument(
+    name = "Saving subscriber preferences in DB",
+    skip(transaction, subscriber, preferences)
+)]
+async fn update_preferences(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber: &SubscriberPreferences,
+    preferences: &UpdatedPreferences,
+    list_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    if subscriber.name != preferences.name.as_ref() {
+        let query = sqlx::query!(
+            r#"UPDATE subscriptions SET name = $1 WHERE id = $2"#,
+            preferences.name.as_ref(),
+            subscriber.id,
+        );
+        transaction.execute(query).await.map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+        record_change(
+            transaction,
+            subscriber.id,
+            "name",
+            &subscriber.name,
+            preferences.name.as_ref(),
+        )
+        .await?;
+    }
+
+    if subscriber.email_format != preferences.format.as_str() {
+        let query = sqlx::query!(
+            r#"UPDATE subscriptions SET email_format = $1 WHERE id = $2"#,
+            preferences.format.as_str(),
+            subscriber.id,
+        );
+        transaction.execute(query).await.map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+        record_change(
+            transaction,
+            subscriber.id,
+            "email_format",
+            &subscriber.email_format,
+            preferences.format.as_str(),
+        )
+        .await?;
+    }
+
+    let previous_lists = sqlx::query!(
+        r#"SELECT lists.slug FROM list_subscriptions
+        JOIN lists ON lists.id = list_subscriptions.list_id
+        WHERE list_subscriptions.subscriber_id = $1
+            AND list_subscriptions.status <> 'unsubscribed'
+        ORDER BY lists.slug"#,
+        subscriber.id,
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?
+    .into_iter()
+    .map(|r| r.slug)
+    .collect::<Vec<_>>();
+
+    let mut requested_lists: Vec<_> = preferences
+        .lists
+        .iter()
+        .map(|l| l.as_ref().to_string())
+        .collect();
+    requested_lists.sort();
+
+    if previous_lists != requested_lists {
+        let query = sqlx::query!(
+            r#"UPDATE list_subscriptions SET status = 'unsubscribed'
+            WHERE subscriber_id = $1 AND NOT (list_id = ANY($2))"#,
+            subscriber.id,
+            list_ids,
+        );
+        transaction.execute(query).await.map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+
+        // New topics inherit the subscriber's confirmation status, re-selected
+        // topics are restored without touching ones that are still active.
+        // While the signup is pending, they join its outstanding confirmation
+        // link, which confirms them along with it.
+        let query = sqlx::query!(
+            r#"
+INSERT INTO list_subscriptions (list_id, subscriber_id, status, subscription_token, subscribed_at)
+SELECT list_id, $2, $3,
+    CASE WHEN $3 = 'pending_confirmation' THEN (
+        SELECT subscription_token FROM subscription_tokens
+        WHERE subscriber_id = $2 AND purpose = 'confirm_subscription'
+        ORDER BY created_at DESC
+        LIMIT 1
+    ) END,
+    $4
+FROM UNNEST($1::uuid[]) AS list_id
+ON CONFLICT (list_id, subscriber_id) DO UPDATE
+SET status = EXCLUDED.status,
+    subscription_token = COALESCE(EXCLUDED.subscription_token, list_subscriptions.subscription_token)
+WHERE list_subscriptions.status = 'unsubscribed'
+"#,
+            list_ids,
+            subscriber.id,
+            subscriber.status,
+            Utc::now(),
+        );
+        transaction.execute(query).await.map_err(|e| {
+            tracing::error!("Failed to execute query: {:?}", e);
+            e
+        })?;
+
+        record_change(
+            transaction,
+            subscriber.id,
+            "lists",
+            &previous_lists.join(","),
+            &requested_lists.join(","),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Recording preference change",
+    skip(transaction, old_value, new_value)
+)]
+pub(crate) async fn record_change(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    field: &str,
+    old_value: &str,
+    new_value: &str,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+INSERT INTO preference_changes (id, subscriber_id, field, old_value, new_value, changed_at)
+VALUES ($1, $2, $3, $4, $5, $6)
+"#,
+        Uuid::new_v4(),
+        subscriber_id,
+        field,
+        old_value,
+        new_value,
+        Utc::now(),
+    );
+    transaction.execute(query).await.map_err(|e| {
+        tracing::error!("Failed to exec query: {:?}", e);
+        e
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use super::{PreferencesFormData, UpdatedPreferences};
+
+    fn form(fields: &[(&str, &str)]) -> Result<PreferencesFormData, String> {
+        fields
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<Vec<_>>()
+            .try_into()
+    }
+
+    #[test]
+    fn repeated_lists_are_collected() {
+        let form = form(&[
+            ("token", "abc"),
+            ("name", "Bobby"),
+            ("format", "text"),
+            ("lists", "daily"),
+            ("lists", "weekly"),
+        ])
+        .unwrap();
+        assert_eq!(form.lists, vec!["daily", "weekly"]);
+    }
+
+    #[test]
+    fn missing_fields_are_rejected() {
+        assert_err!(form(&[("token", "abc"), ("name", "Bobby")]));
+    }
+
+    #[test]
+    fn names_are_validated_like_at_signup() {
+        let form = form(&[("token", "abc"), ("name", "<Bobby>"), ("format", "html")]).unwrap();
+        assert_err!(UpdatedPreferences::try_from(&form));
+    }
+
+    #[test]
+    fn no_lists_is_a_valid_choice() {
+        let form = form(&[("token", "abc"), ("name", "Bobby"), ("format", "html")]).unwrap();
+        assert_ok!(UpdatedPreferences::try_from(&form));
+    }
+}
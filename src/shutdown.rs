@@ -0,0 +1,101 @@
+use std::{future::Future, time::Duration};
+
+use tokio_util::{
+    sync::CancellationToken,
+    task::{
+        task_tracker::{TaskTrackerToken, TrackedFuture},
+        TaskTracker,
+    },
+};
+
+/// Coordinates an orderly stop of the application: once triggered, the HTTP
+/// servers stop accepting connections and drain, and background workers are
+/// asked to finish their current unit of work before the process exits.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    requests: TaskTracker,
+    workers: TaskTracker,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn trigger(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Resolves once a shutdown has been triggered.
+    pub async fn requested(&self) {
+        self.token.cancelled().await
+    }
+
+    /// Wraps the handling of a request so that shutdown can wait for it.
+    pub fn track_request<F: Future>(&self, request: F) -> TrackedFuture<F> {
+        self.requests.track_future(request)
+    }
+
+    /// Keeps a request counted as in flight until the token is dropped, for
+    /// responses whose body is still streamed once the handler returned.
+    pub fn track_response(&self) -> TaskTrackerToken {
+        self.requests.token()
+    }
+
+    /// Waits for every in-flight request to be handled, up to `deadline`.
+    /// Returns whether they all were.
+    pub async fn wait_for_requests(&self, deadline: Duration) -> bool {
+        self.requests.close();
+        tokio::time::timeout(deadline, self.requests.wait())
+            .await
+            .is_ok()
+    }
+
+    /// Spawns a background worker that shutdown will wait for. Workers should
+    /// check [`Shutdown::is_triggered`] between units of work and return
+    /// once it is set.
+    pub fn spawn_worker<F>(&self, worker: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.workers.spawn(worker);
+    }
+
+    /// Waits for every worker to return, up to `deadline`. Returns whether
+    /// they all did.
+    pub async fn wait_for_workers(&self, deadline: Duration) -> bool {
+        self.workers.close();
+        tokio::time::timeout(deadline, self.workers.wait())
+            .await
+            .is_ok()
+    }
+}
+
+/// Resolves when the process is asked to stop, by SIGTERM or Ctrl-C.
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl-C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
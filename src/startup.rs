@@ -1,90 +1,498 @@
-use std::net::TcpListener;
+use std::{
+    fs::File,
+    io::BufReader,
+    net::TcpListener,
+    time::{Duration, Instant},
+};
 
-use actix_web::{dev::Server, web, App, HttpServer};
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use actix_cors::Cors;
+use actix_web::{
+    dev::{Server, Service},
+    http::{header, KeepAlive},
+    middleware::DefaultHeaders,
+    web, App, HttpMessage, HttpServer,
+};
+use sqlx::{
+    migrate::{Migration, Migrator},
+    postgres::PgPoolOptions,
+    Executor, PgPool,
+};
 use tracing_actix_web::TracingLogger;
 
 use crate::{
-    configuration::{DatabaseSettings, Settings},
+    config_reload::watch_config_until_stopped,
+    configuration::{
+        ApplicationSettings, CorsSettings, DatabaseSettings, HealthSettings,
+        SecurityHeadersSettings, Settings, TlsSettings,
+    },
+    db_pools::DbPools,
     email_client::EmailClient,
-    routes::{get_health, post_subscribe, subscription_confirm},
+    listener::{ListenAddress, Listener, SocketActivation},
+    metrics::{get_metrics, record_http_request},
+    request_id::{attach_request_id, RequestId},
+    routes::{
+        confirm_email_change, create_list, create_segment, export_subscribers, get_health,
+        get_lists, get_log_level, get_preferences, get_readiness, get_segments, list_subscribers,
+        post_api_subscription, post_change_email, post_preferences, post_subscribe,
+        problem_json_config, publish_newsletter, put_log_level, put_subscriber_attributes,
+        subscription_confirm,
+    },
+    shutdown::{self, Shutdown},
+    telemetry::{LogFilter, RequestRootSpanBuilder},
 };
 
 pub struct Application {
-    port: u16,
+    address: ListenAddress,
     pub server: Server,
+    metrics_port: Option<u16>,
+    metrics_server: Option<Server>,
+    db_pools: DbPools,
+    email_client: EmailClient,
+    shutdown: Shutdown,
+    shutdown_deadline: Duration,
 }
 
 impl Application {
-    pub async fn build(config: Settings) -> Result<Self, std::io::Error> {
+    pub async fn build(
+        config: Settings,
+        log_filter: LogFilter,
+        socket_activation: Option<SocketActivation>,
+    ) -> Result<Self, std::io::Error> {
         let connection_pool = get_connection_pool(&config.database);
+        let replica_pool = config
+            .database
+            .replica_settings()
+            .map(|replica| get_connection_pool(&replica));
+        let db_pools = DbPools::new(connection_pool.clone(), replica_pool);
 
-        let sender_email = config
-            .email_client
-            .sender()
-            .expect("Invalid sender email addr");
+        let email_client = config.email_client.clone().client();
 
-        let timeout = config.email_client.timeout();
+        let listener = Listener::bind(&config.application, socket_activation)?;
+        let address = listener.address()?;
 
-        let email_client = EmailClient::new(
-            config.email_client.base_url,
-            sender_email,
-            config.email_client.authorization_token,
-            timeout,
-        );
+        let metrics_listener = config
+            .application
+            .metrics_port
+            .map(|metrics_port| {
+                TcpListener::bind(format!("{}:{}", config.application.host, metrics_port))
+            })
+            .transpose()?;
+        let metrics_port = metrics_listener
+            .as_ref()
+            .map(|listener| listener.local_addr().unwrap().port());
+        let shutdown_deadline = config.application.shutdown_deadline();
+        let shutdown = Shutdown::new();
+        if let Some(replica) = &config.database.replica {
+            db_pools.check_replica(config.health.timeout()).await;
+            shutdown.spawn_worker(db_pools.clone().monitor_replica(
+                replica.health_check_interval(),
+                config.health.timeout(),
+                shutdown.clone(),
+            ));
+        }
+        if let Some(interval) = config.application.config_reload_interval() {
+            shutdown.spawn_worker(watch_config_until_stopped(
+                config.clone(),
+                email_client.clone(),
+                log_filter.clone(),
+                interval,
+                shutdown.clone(),
+            ));
+        }
+        let metrics_server = metrics_listener
+            .map(|listener| run_metrics(listener, connection_pool.clone(), shutdown_deadline))
+            .transpose()?;
 
-        let server_address = format!("{}:{}", config.application.host, config.application.port);
-
-        let listener = TcpListener::bind(server_address)?;
-        let port = listener.local_addr().unwrap().port();
         let server = run(
             listener,
-            connection_pool,
-            email_client,
-            config.application.base_url,
+            db_pools.clone(),
+            email_client.clone(),
+            config.application,
+            config.health,
+            log_filter,
+            shutdown.clone(),
         )?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            address,
+            server,
+            metrics_port,
+            metrics_server,
+            db_pools,
+            email_client,
+            shutdown,
+            shutdown_deadline,
+        })
+    }
+
+    /// Where the main listener accepts connections.
+    pub fn address(&self) -> &ListenAddress {
+        &self.address
+    }
+
+    /// The port `/metrics` is served on when it has a listener of its own.
+    pub fn metrics_port(&self) -> Option<u16> {
+        self.metrics_port
+    }
+
+    /// The primary pool, closed once the background workers are done.
+    pub fn db_pool(&self) -> PgPool {
+        self.db_pools.writer().clone()
+    }
+
+    /// Shares its timeout with the client used to serve requests, so that
+    /// configuration reloads apply to both.
+    pub fn email_client(&self) -> EmailClient {
+        self.email_client.clone()
     }
 
-    pub fn port(&self) -> u16 {
-        self.port
+    /// Coordinates the shutdown of this application and its background workers.
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
     }
 
+    /// Serves requests until SIGTERM, Ctrl-C or [`Shutdown::trigger`], then
+    /// drains in-flight requests and workers and closes the database pools.
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        let server_handle = self.server.handle();
+        let metrics_handle = self.metrics_server.as_ref().map(Server::handle);
+        let shutdown = self.shutdown;
+
+        let servers = async {
+            let result = match self.metrics_server {
+                Some(metrics_server) => tokio::try_join!(self.server, metrics_server).map(|_| ()),
+                None => self.server.await,
+            };
+            // Whatever stopped the servers, the rest of the app follows
+            shutdown.trigger();
+            result
+        };
+
+        let drain = async {
+            tokio::select! {
+                _ = shutdown::signal() => shutdown.trigger(),
+                _ = shutdown.requested() => {}
+            }
+            tracing::info!("Shutting down");
+            let deadline = Instant::now() + self.shutdown_deadline;
+
+            // Stops accepting connections and lets in-flight requests finish
+            // before stopping the servers: actix-server may drop a worker's
+            // open connections when its accept loop exits first
+            server_handle.pause().await;
+            if let Some(metrics_handle) = &metrics_handle {
+                metrics_handle.pause().await;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if !shutdown.wait_for_requests(remaining).await {
+                tracing::warn!("In-flight requests did not finish before the shutdown deadline");
+            }
+            match metrics_handle {
+                Some(metrics_handle) => {
+                    tokio::join!(server_handle.stop(true), metrics_handle.stop(true));
+                }
+                None => server_handle.stop(true).await,
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if !shutdown.wait_for_workers(remaining).await {
+                tracing::warn!("Background workers did not finish before the shutdown deadline");
+            }
+
+            self.db_pools.close().await;
+        };
+
+        let (result, ()) = tokio::join!(servers, drain);
+        result
     }
 }
 
 pub struct ApplicationBaseUrl(pub String);
 
+/// Migrations embedded at compile time, the schema version this build expects.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// The embedded migrations not yet applied to the database, oldest first.
+pub async fn pending_migrations(pool: &PgPool) -> Result<Vec<&'static Migration>, sqlx::Error> {
+    let has_migrations_table: bool =
+        sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+            .fetch_one(pool)
+            .await?;
+    let applied: Vec<i64> = if has_migrations_table {
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(pool)
+            .await?
+    } else {
+        Vec::new()
+    };
+
+    Ok(MIGRATOR
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .collect())
+}
+
 pub fn get_connection_pool(db_cfg: &DatabaseSettings) -> PgPool {
-    PgPoolOptions::new().connect_lazy_with(db_cfg.with_db())
+    let statement_timeout = db_cfg.statement_timeout_ms;
+    PgPoolOptions::new()
+        .max_connections(db_cfg.max_connections)
+        .min_connections(db_cfg.min_connections)
+        .acquire_timeout(db_cfg.acquire_timeout())
+        .idle_timeout(db_cfg.idle_timeout())
+        .max_lifetime(db_cfg.max_lifetime())
+        .after_connect(move |connection, _| {
+            Box::pin(async move {
+                if let Some(statement_timeout) = statement_timeout {
+                    connection
+                        .execute(format!("SET statement_timeout = {}", statement_timeout).as_str())
+                        .await?;
+                }
+                Ok(())
+            })
+        })
+        .connect_lazy_with(db_cfg.with_db())
+}
+
+/// Connects to run migrations, which may lock or rewrite tables for longer
+/// than any statement timeout the app or the database role is held to.
+pub async fn get_migration_pool(db_cfg: &DatabaseSettings) -> Result<PgPool, sqlx::Error> {
+    PgPoolOptions::new()
+        .max_connections(1)
+        .after_connect(|connection, _| {
+            Box::pin(async move {
+                connection.execute("SET statement_timeout = 0").await?;
+                Ok(())
+            })
+        })
+        .connect_with(db_cfg.with_db())
+        .await
 }
 
 pub fn run(
-    listener: TcpListener,
-    connection_pool: PgPool,
+    listener: Listener,
+    db_pools: DbPools,
     email_client: EmailClient,
-    base_url: String,
+    application: ApplicationSettings,
+    health: HealthSettings,
+    log_filter: LogFilter,
+    shutdown: Shutdown,
 ) -> Result<Server, std::io::Error> {
-    let db_pool = web::Data::new(connection_pool);
+    let serve_metrics = application.metrics_port.is_none();
+    let shutdown_deadline = application.shutdown_deadline();
+    let tls_config = application.tls.as_ref().map(rustls_config).transpose()?;
+    let keep_alive = match application.keep_alive() {
+        Duration::ZERO => KeepAlive::Disabled,
+        timeout => KeepAlive::Timeout(timeout),
+    };
+    let max_form_payload_bytes = application.max_form_payload_bytes;
+    let security_headers = application.security_headers.clone();
+    let cors_settings = application.cors.clone();
+    let client_request_timeout = application.client_request_timeout();
+    let client_disconnect_timeout = application.client_disconnect_timeout();
+    let workers = application.workers;
+    let db_pool = web::Data::new(db_pools.writer().clone());
+    let db_pools = web::Data::new(db_pools);
     let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let confirmation = web::Data::new(application.confirmation.clone());
+    let base_url = web::Data::new(ApplicationBaseUrl(application.base_url));
+    let health = web::Data::new(health);
+    let log_filter = web::Data::new(log_filter);
+    let shutdown = web::Data::new(shutdown);
     let server = HttpServer::new(move || {
-        App::new()
-            .wrap(TracingLogger::default())
+        let request_shutdown = shutdown.clone();
+        let app = App::new()
+            .wrap_fn(move |req, srv| request_shutdown.track_request(srv.call(req)))
+            .wrap_fn(|req, srv| {
+                let request_id = req
+                    .extensions()
+                    .get::<RequestId>()
+                    .cloned()
+                    .unwrap_or_else(RequestId::generate);
+                let response = request_id.scope(srv.call(req));
+                async move { attach_request_id(response.await?).await }
+            })
+            .wrap_fn(|req, srv| {
+                let start = Instant::now();
+                let method = req.method().to_string();
+                let response = srv.call(req);
+                async move {
+                    let response = response.await?;
+                    let route = response.request().match_pattern();
+                    record_http_request(
+                        &method,
+                        route.as_deref().unwrap_or("unmatched"),
+                        response.status().as_u16(),
+                        start.elapsed(),
+                    );
+                    Ok(response)
+                }
+            })
+            .wrap(cors(&cors_settings))
+            .wrap(default_headers(&security_headers))
+            .wrap(TracingLogger::<RequestRootSpanBuilder>::new())
             .route("/health", web::get().to(get_health))
+            .route("/health/live", web::get().to(get_health))
+            .route("/health/ready", web::get().to(get_readiness))
             .route("/subscribe", web::post().to(post_subscribe))
+            .service(
+                web::resource("/api/v1/subscriptions")
+                    .app_data(problem_json_config())
+                    .route(web::post().to(post_api_subscription)),
+            )
             .route(
                 "/subscriptions/confirm",
                 web::get().to(subscription_confirm),
             )
+            .route("/subscriptions/preferences", web::get().to(get_preferences))
+            .route(
+                "/subscriptions/preferences",
+                web::post().to(post_preferences),
+            )
+            .route("/subscriptions/email", web::post().to(post_change_email))
+            .route(
+                "/subscriptions/email/confirm",
+                web::get().to(confirm_email_change),
+            )
+            .route("/admin/subscribers", web::get().to(list_subscribers))
+            .route(
+                "/admin/subscribers/export",
+                web::get().to(export_subscribers),
+            )
+            .route(
+                "/admin/subscribers/{subscriber_id}/attributes",
+                web::put().to(put_subscriber_attributes),
+            )
+            .route("/admin/segments", web::get().to(get_segments))
+            .route("/admin/segments", web::post().to(create_segment))
+            .route("/admin/lists", web::get().to(get_lists))
+            .route("/admin/lists", web::post().to(create_list))
+            .route("/admin/newsletters", web::post().to(publish_newsletter))
+            .route("/admin/log-level", web::get().to(get_log_level))
+            .route("/admin/log-level", web::put().to(put_log_level))
             .app_data(db_pool.clone())
+            .app_data(db_pools.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(confirmation.clone())
+            .app_data(health.clone())
+            .app_data(log_filter.clone())
+            .app_data(shutdown.clone())
+            .app_data(web::FormConfig::default().limit(max_form_payload_bytes));
+        if serve_metrics {
+            app.route("/metrics", web::get().to(get_metrics))
+        } else {
+            app
+        }
+    })
+    .keep_alive(keep_alive)
+    .client_request_timeout(client_request_timeout)
+    .client_disconnect_timeout(client_disconnect_timeout);
+    let server = match workers {
+        Some(workers) => server.workers(workers),
+        None => server,
+    };
+    let server = match (listener, tls_config) {
+        (Listener::Tcp(listener), Some(tls_config)) => {
+            server.listen_rustls_0_21(listener, tls_config)?
+        }
+        (Listener::Tcp(listener), None) => server.listen(listener)?,
+        (Listener::Unix(listener), None) => server.listen_uds(listener)?,
+        (Listener::Unix(_), Some(_)) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "TLS is not supported on Unix sockets",
+            ))
+        }
+    };
+    let server = server
+        .disable_signals()
+        .shutdown_timeout(shutdown_deadline.as_secs())
+        .run();
+    Ok(server)
+}
+
+fn default_headers(settings: &SecurityHeadersSettings) -> DefaultHeaders {
+    let headers = DefaultHeaders::new()
+        .add((
+            header::CONTENT_SECURITY_POLICY,
+            settings.content_security_policy.as_str(),
+        ))
+        .add((header::X_CONTENT_TYPE_OPTIONS, "nosniff"))
+        .add((header::REFERRER_POLICY, settings.referrer_policy.as_str()));
+    match settings.hsts_max_age_secs {
+        Some(max_age) => headers.add((
+            header::STRICT_TRANSPORT_SECURITY,
+            format!("max-age={}", max_age),
+        )),
+        None => headers,
+    }
+}
+
+fn cors(settings: &CorsSettings) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods(settings.allowed_methods.iter().map(String::as_str))
+        .allowed_headers(settings.allowed_headers.iter().map(String::as_str))
+        .max_age(settings.max_age_secs);
+    for origin in &settings.allowed_origins {
+        cors = match origin.as_str() {
+            "*" => cors.allow_any_origin(),
+            origin => cors.allowed_origin(origin),
+        };
+    }
+    if settings.allow_credentials {
+        cors = cors.supports_credentials();
+    }
+    cors
+}
+
+/// Reads the certificate chain and private key to serve HTTPS with.
+fn rustls_config(tls: &TlsSettings) -> Result<rustls::ServerConfig, std::io::Error> {
+    let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+
+    let mut cert_file = BufReader::new(File::open(&tls.cert)?);
+    let certs: Vec<_> = rustls_pemfile::certs(&mut cert_file)?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+    if certs.is_empty() {
+        return Err(invalid(format!("No certificate in {}", tls.cert.display())));
+    }
+
+    let mut key_file = BufReader::new(File::open(&tls.key)?);
+    let key = loop {
+        match rustls_pemfile::read_one(&mut key_file)? {
+            Some(
+                rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::ECKey(key),
+            ) => break rustls::PrivateKey(key),
+            Some(_) => continue,
+            None => return Err(invalid(format!("No private key in {}", tls.key.display()))),
+        }
+    };
+
+    rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| invalid(e.to_string()))
+}
+
+/// Serves `/metrics` alone, for when it is kept off the main listener.
+pub fn run_metrics(
+    listener: TcpListener,
+    connection_pool: PgPool,
+    shutdown_deadline: Duration,
+) -> Result<Server, std::io::Error> {
+    let db_pool = web::Data::new(connection_pool);
+    let server = HttpServer::new(move || {
+        App::new()
+            .route("/metrics", web::get().to(get_metrics))
+            .app_data(db_pool.clone())
     })
     .listen(listener)?
+    .disable_signals()
+    .shutdown_timeout(shutdown_deadline.as_secs())
     .run();
     Ok(server)
 }
@@ -1,27 +1,897 @@
-use tracing::subscriber::set_global_default;
-use tracing::Subscriber;
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    Error,
+};
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use opentelemetry::{
+    global,
+    propagation::Extractor,
+    trace::{TraceContextExt, TraceError, TracerProvider as _},
+    KeyValue,
+};
+use opentelemetry_http::HeaderInjector;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{self, Tracer, TracerProvider},
+    Resource,
+};
+use reqwest::header::HeaderMap;
+use sha2::{Digest, Sha256};
+use tracing::{
+    field::{self, DisplayValue, Field, FieldSet, Value, ValueSet, Visit},
+    level_filters::LevelFilter,
+    span::{Attributes, Id, Record},
+    subscriber::{set_global_default, Interest},
+    Dispatch, Event, Metadata, Span, Subscriber,
+};
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
-use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    fmt::MakeWriter,
+    layer::{Context, Layer, SubscriberExt},
+    registry::LookupSpan,
+    reload, EnvFilter, Registry,
+};
+
+use crate::{
+    configuration::{RedactionMode, RedactionSettings, TelemetrySettings},
+    request_id::RequestId,
+};
 
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
-) -> impl Subscriber + Sync + Send
+    tracer: Tracer,
+    redaction: RedactionSettings,
+) -> (impl Subscriber + Sync + Send, LogFilter)
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
+    let (env_filter, handle) = reload::Layer::new(env_filter);
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
-    Registry::default()
+    let subscriber = Registry::default()
         .with(env_filter)
-        .with(JsonStorageLayer)
-        .with(formatting_layer)
+        .with(RedactingLayer::new(
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer)
+                .and_then(JsonStorageLayer)
+                .and_then(formatting_layer),
+            Redactor::new(redaction),
+        ));
+    (subscriber, LogFilter::new(handle))
+}
+
+/// Handle to change the log filter of a running subscriber.
+#[derive(Clone)]
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    pending_revert: Arc<Mutex<PendingRevert>>,
+}
+
+/// The revert scheduled by the latest temporary change, if any. Every change
+/// bumps `generation`, so a revert that was superseded knows to stand down.
+#[derive(Default)]
+struct PendingRevert {
+    generation: u64,
+    directive: Option<String>,
+    /// Whether the filter was changed with [`LogFilter::set`] since it was
+    /// last configured, or reverted.
+    overridden: bool,
+}
+
+/// What [`LogFilter::configure`] did with the configured filter.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Configured {
+    Applied,
+    /// A temporary override is in place, the filter is applied when it expires.
+    AfterOverride,
+    /// The filter was overridden for good, it is applied on the next restart.
+    Overridden,
+}
+
+impl LogFilter {
+    pub(crate) fn new(handle: reload::Handle<EnvFilter, Registry>) -> Self {
+        Self {
+            handle,
+            pending_revert: Arc::new(Mutex::new(PendingRevert::default())),
+        }
+    }
+
+    /// The filter currently applied, as an env-filter directive.
+    pub fn current(&self) -> Result<String, String> {
+        self.handle
+            .with_current(|filter| filter.to_string())
+            .map_err(|e| e.to_string())
+    }
+
+    /// Replaces the filter with `directive`. With a `ttl`, the filter in
+    /// place before this change (or before the temporary change it replaces)
+    /// is restored once it elapses.
+    pub fn set(&self, directive: &str, ttl: Option<Duration>) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directive).map_err(|e| e.to_string())?;
+
+        let mut pending = self.pending_revert.lock().unwrap();
+        let restore = match pending.directive.take() {
+            Some(directive) => directive,
+            None => self.current()?,
+        };
+        self.handle.reload(filter).map_err(|e| e.to_string())?;
+        pending.generation += 1;
+        pending.overridden = true;
+
+        if let Some(ttl) = ttl {
+            pending.directive = Some(restore);
+            let generation = pending.generation;
+            let log_filter = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(ttl).await;
+                log_filter.revert(generation);
+            });
+        }
+        Ok(())
+    }
+
+    /// Replaces the filter taken from the configuration with `directive`,
+    /// leaving a filter changed with [`LogFilter::set`] in place.
+    pub fn configure(&self, directive: &str) -> Result<Configured, String> {
+        let filter = EnvFilter::try_new(directive).map_err(|e| e.to_string())?;
+
+        let mut pending = self.pending_revert.lock().unwrap();
+        if pending.directive.is_some() {
+            pending.directive = Some(directive.to_string());
+            return Ok(Configured::AfterOverride);
+        }
+        if pending.overridden {
+            return Ok(Configured::Overridden);
+        }
+        self.handle.reload(filter).map_err(|e| e.to_string())?;
+        Ok(Configured::Applied)
+    }
+
+    fn revert(&self, generation: u64) {
+        let mut pending = self.pending_revert.lock().unwrap();
+        if pending.generation != generation {
+            return;
+        }
+        let Some(directive) = pending.directive.take() else {
+            return;
+        };
+        pending.overridden = false;
+        match EnvFilter::try_new(&directive).map(|filter| self.handle.reload(filter)) {
+            Ok(Ok(())) => tracing::info!("Log filter reverted to {}", directive),
+            Ok(Err(e)) => tracing::error!("Failed to revert log filter: {}", e),
+            Err(e) => tracing::error!("Failed to revert log filter: {}", e),
+        }
+    }
+}
+
+/// Decides which fields may hold personal data (emails, names, tokens) and
+/// masks their values.
+#[derive(Clone, Debug)]
+pub struct Redactor {
+    settings: RedactionSettings,
+}
+
+impl Redactor {
+    pub fn new(settings: RedactionSettings) -> Self {
+        Self { settings }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.settings.mode != RedactionMode::Off
+    }
+
+    fn is_sensitive(&self, field: &str) -> bool {
+        self.settings.fields.iter().any(|name| {
+            field == name
+                || field
+                    .strip_suffix(name.as_str())
+                    .is_some_and(|prefix| prefix.ends_with('_'))
+        })
+    }
+
+    fn mask(&self, value: &str) -> String {
+        match self.settings.mode {
+            RedactionMode::Redact => String::from("[REDACTED]"),
+            RedactionMode::Hash => {
+                let digest = Sha256::digest(value.as_bytes());
+                let hex: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+                format!("sha256:{hex}")
+            }
+            RedactionMode::Off => value.to_string(),
+        }
+    }
+
+    /// Records `values` with the sensitive ones masked, or `None` when none
+    /// of them is sensitive and they can be passed on as they are.
+    fn redact(&self, fields: &FieldSet, values: impl FnOnce(&mut dyn Visit)) -> Option<Redacted> {
+        if !self.is_enabled() || !fields.iter().any(|field| self.is_sensitive(field.name())) {
+            return None;
+        }
+        let mut recorder = Recorder {
+            redactor: self,
+            values: Vec::new(),
+            secrets: Vec::new(),
+            message: None,
+        };
+        values(&mut recorder);
+        recorder.finish()
+    }
+}
+
+/// Spans and events have at most 32 fields.
+const MAX_FIELDS: usize = 32;
+
+enum RecordedValue {
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Bool(bool),
+    Str(String),
+    Debug(DisplayValue<String>),
+}
+
+impl RecordedValue {
+    fn as_value(&self) -> &dyn Value {
+        match self {
+            Self::I64(value) => value,
+            Self::U64(value) => value,
+            Self::F64(value) => value,
+            Self::Bool(value) => value,
+            Self::Str(value) => value,
+            Self::Debug(value) => value,
+        }
+    }
+}
+
+/// Collects the values of a span or event, masking the sensitive ones along
+/// with their occurrences in the message.
+struct Recorder<'a> {
+    redactor: &'a Redactor,
+    values: Vec<(Field, RecordedValue)>,
+    secrets: Vec<String>,
+    message: Option<(Field, String)>,
+}
+
+impl Recorder<'_> {
+    fn push(&mut self, field: &Field, value: RecordedValue, text: impl FnOnce() -> String) {
+        let value = if self.redactor.is_sensitive(field.name()) {
+            let text = text();
+            let masked = self.redactor.mask(&text);
+            self.secrets.push(text);
+            RecordedValue::Str(masked)
+        } else {
+            value
+        };
+        self.values.push((field.clone(), value));
+    }
+
+    fn finish(mut self) -> Option<Redacted> {
+        if self.secrets.is_empty() {
+            return None;
+        }
+        if let Some((field, mut message)) = self.message.take() {
+            for secret in self.secrets.iter().filter(|secret| !secret.is_empty()) {
+                message = message.replace(secret.as_str(), &self.redactor.mask(secret));
+            }
+            self.values
+                .push((field, RecordedValue::Debug(field::display(message))));
+        }
+        Some(Redacted {
+            values: self.values,
+        })
+    }
+}
+
+impl Visit for Recorder<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.push(field, RecordedValue::I64(value), || value.to_string());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.push(field, RecordedValue::U64(value), || value.to_string());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.push(field, RecordedValue::F64(value), || value.to_string());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.push(field, RecordedValue::Bool(value), || value.to_string());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push(field, RecordedValue::Str(value.to_string()), || {
+            value.to_string()
+        });
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        let text = format!("{value:?}");
+        if field.name() == "message" && !self.redactor.is_sensitive(field.name()) {
+            self.message = Some((field.clone(), text));
+            return;
+        }
+        self.push(
+            field,
+            RecordedValue::Debug(field::display(text.clone())),
+            || text,
+        );
+    }
+}
+
+/// The values of a span or event after redaction.
+struct Redacted {
+    values: Vec<(Field, RecordedValue)>,
+}
+
+impl Redacted {
+    fn with_value_set<R>(&self, fields: &FieldSet, f: impl FnOnce(&ValueSet<'_>) -> R) -> R {
+        // Value sets are built from fixed-size arrays: unused slots repeat a
+        // field without a value, which is skipped when recording
+        let padding = &self.values[0].0;
+        let mut values: [(&Field, Option<&dyn Value>); MAX_FIELDS] = [(padding, None); MAX_FIELDS];
+        for (slot, (field, value)) in values.iter_mut().zip(&self.values) {
+            *slot = (field, Some(value.as_value()));
+        }
+        f(&fields.value_set(&values))
+    }
+}
+
+/// Wraps the layers writing spans and events out, masking sensitive field
+/// values as they are recorded. The wrapped layers, and so every sink (the
+/// Bunyan output and the OTLP collector alike), only ever see masked values.
+pub struct RedactingLayer<L> {
+    inner: L,
+    redactor: Redactor,
+}
+
+impl<L> RedactingLayer<L> {
+    pub fn new(inner: L, redactor: Redactor) -> Self {
+        Self { inner, redactor }
+    }
+}
+
+impl<S, L> Layer<S> for RedactingLayer<L>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    L: Layer<S>,
+{
+    fn on_register_dispatch(&self, subscriber: &Dispatch) {
+        self.inner.on_register_dispatch(subscriber)
+    }
+
+    fn on_layer(&mut self, subscriber: &mut S) {
+        self.inner.on_layer(subscriber)
+    }
+
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.inner.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.enabled(metadata, ctx)
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let metadata = attrs.metadata();
+        let Some(redacted) = self
+            .redactor
+            .redact(metadata.fields(), |visitor| attrs.record(visitor))
+        else {
+            return self.inner.on_new_span(attrs, id, ctx);
+        };
+        redacted.with_value_set(metadata.fields(), |values| {
+            let attrs = if attrs.is_root() {
+                Attributes::new_root(metadata, values)
+            } else if let Some(parent) = attrs.parent() {
+                Attributes::child_of(parent.clone(), metadata, values)
+            } else {
+                Attributes::new(metadata, values)
+            };
+            self.inner.on_new_span(&attrs, id, ctx)
+        })
+    }
+
+    fn on_record(&self, span: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(metadata) = ctx.metadata(span) else {
+            return self.inner.on_record(span, values, ctx);
+        };
+        let Some(redacted) = self
+            .redactor
+            .redact(metadata.fields(), |visitor| values.record(visitor))
+        else {
+            return self.inner.on_record(span, values, ctx);
+        };
+        redacted.with_value_set(metadata.fields(), |values| {
+            self.inner.on_record(span, &Record::new(values), ctx)
+        })
+    }
+
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, S>) {
+        self.inner.on_follows_from(span, follows, ctx)
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.event_enabled(event, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let Some(redacted) = self
+            .redactor
+            .redact(metadata.fields(), |visitor| event.record(visitor))
+        else {
+            return self.inner.on_event(event, ctx);
+        };
+        redacted.with_value_set(metadata.fields(), |values| {
+            let event = if event.is_root() {
+                Event::new_child_of(None, metadata, values)
+            } else if let Some(parent) = event.parent() {
+                Event::new_child_of(parent.clone(), metadata, values)
+            } else {
+                Event::new(metadata, values)
+            };
+            self.inner.on_event(&event, ctx)
+        })
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_enter(id, ctx)
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_exit(id, ctx)
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        self.inner.on_close(id, ctx)
+    }
+
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
+        self.inner.on_id_change(old, new, ctx)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.inner.max_level_hint()
+    }
+
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            Some(self as *const Self as *const ())
+        } else {
+            self.inner.downcast_raw(id)
+        }
+    }
+}
+
+/// Builds the tracer backing the OpenTelemetry layer. Spans are batched to
+/// the OTLP collector when one is configured; otherwise they are only used
+/// to carry trace ids through the process.
+///
+/// Exporting spawns onto the Tokio runtime, so this must be called from
+/// within one when an endpoint is set.
+pub fn get_tracer(settings: &TelemetrySettings) -> Result<Tracer, TraceError> {
+    let config = trace::config().with_resource(Resource::new(vec![KeyValue::new(
+        "service.name",
+        settings.service_name.clone(),
+    )]));
+
+    let provider = match &settings.otlp_endpoint {
+        Some(endpoint) => {
+            let exporter = opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(endpoint)
+                .build_span_exporter()?;
+            TracerProvider::builder()
+                .with_batch_exporter(exporter, runtime::Tokio)
+                .with_config(config)
+                .build()
+        }
+        None => TracerProvider::builder().with_config(config).build(),
+    };
+    let tracer = provider.tracer(settings.service_name.clone());
+    // The tracer only holds a weak reference to its provider
+    global::set_tracer_provider(provider);
+    Ok(tracer)
 }
 
 pub fn init_subscriber(subscriber: impl Subscriber + Sync + Send) {
     LogTracer::init().expect("Failed to set LogTracer");
+    global::set_text_map_propagator(TraceContextPropagator::new());
     set_global_default(subscriber).expect("Failed to set subscriber");
 }
+
+/// W3C `traceparent` headers for the current span, to continue the trace in
+/// the services we call.
+pub fn trace_context_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
+}
+
+/// The `traceparent` of the current span, to continue the trace from work
+/// that happens outside of it.
+pub fn current_traceparent() -> Option<String> {
+    trace_context_headers()
+        .get("traceparent")
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
+
+/// Makes `span` part of the trace a [`current_traceparent`] came from.
+pub fn continue_trace(span: &Span, traceparent: &str) {
+    let carrier = HashMap::from([(String::from("traceparent"), traceparent.to_string())]);
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(&carrier));
+    span.set_parent(parent);
+}
+
+/// Root span of every request: carries its [`RequestId`] and continues the
+/// caller's trace when it sent a `traceparent` header.
+pub struct RequestRootSpanBuilder;
+
+impl RootSpanBuilder for RequestRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let request_id = RequestId::assign(request);
+        let method = request.method();
+        let route = request
+            .match_pattern()
+            .unwrap_or_else(|| String::from("default"));
+        let span = tracing::info_span!(
+            "HTTP request",
+            http.method = %method,
+            http.route = %route,
+            // Query strings carry confirmation and preference tokens
+            http.target = %request.path(),
+            http.client_ip = %request.connection_info().realip_remote_addr().unwrap_or(""),
+            http.status_code = field::Empty,
+            otel.name = %format!("HTTP {} {}", method, route),
+            otel.kind = "server",
+            otel.status_code = field::Empty,
+            trace_id = field::Empty,
+            request_id = %request_id,
+            exception.message = field::Empty,
+            exception.details = field::Empty,
+        );
+
+        let parent = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&RequestHeaders(request.headers()))
+        });
+        span.set_parent(parent);
+        let trace_id = span.context().span().span_context().trace_id();
+        span.record("trace_id", field::display(trace_id));
+        span
+    }
+
+    fn on_request_end<B: MessageBody>(span: Span, outcome: &Result<ServiceResponse<B>, Error>) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
+struct RequestHeaders<'a>(&'a actix_web::http::header::HeaderMap);
+
+impl Extractor for RequestHeaders<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use opentelemetry_proto::tonic::{
+        collector::trace::v1::ExportTraceServiceRequest, common::v1::any_value,
+    };
+    use prost::Message;
+    use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+    use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, Layer};
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use actix_web::{test as actix_test, web, App, HttpResponse};
+    use tracing_actix_web::TracingLogger;
+
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+    use tracing::Subscriber;
+
+    use super::{get_tracer, RedactingLayer, Redactor, RequestRootSpanBuilder};
+    use crate::configuration::{RedactionMode, RedactionSettings, TelemetrySettings};
+
+    fn redaction(mode: RedactionMode) -> RedactionSettings {
+        RedactionSettings {
+            mode,
+            fields: vec![String::from("email"), String::from("token")],
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for CapturedLogs {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    /// Logs to `logs` and exports spans through `tracer`, like the app does.
+    fn subscriber(
+        logs: &CapturedLogs,
+        tracer: Tracer,
+        mode: RedactionMode,
+    ) -> impl Subscriber + Send + Sync {
+        tracing_subscriber::registry().with(RedactingLayer::new(
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer)
+                .and_then(JsonStorageLayer)
+                .and_then(BunyanFormattingLayer::new(
+                    String::from("test"),
+                    logs.clone(),
+                )),
+            Redactor::new(redaction(mode)),
+        ))
+    }
+
+    fn unexported_tracer() -> Tracer {
+        TracerProvider::builder().build().tracer("test")
+    }
+
+    fn log_signup(mode: RedactionMode) -> String {
+        let logs = CapturedLogs::default();
+        let subscriber = subscriber(&logs, unexported_tracer(), mode);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!(
+                "Adding a new subscriber",
+                subscriber_email = "ursula_le_guin@gmail.com",
+                subscriber_lists = "newsletter",
+                subscription_token = tracing::field::Empty,
+            );
+            span.record("subscription_token", "s3cr3t");
+            span.in_scope(|| {
+                tracing::info!(
+                    confirmation_email = "ursula_le_guin@gmail.com",
+                    "Subscriber stored"
+                )
+            });
+        });
+
+        let logs = logs.0.lock().unwrap().clone();
+        String::from_utf8(logs).unwrap()
+    }
+
+    #[test]
+    fn sensitive_span_fields_are_redacted() {
+        let logs = log_signup(RedactionMode::Redact);
+
+        assert!(!logs.contains("ursula_le_guin@gmail.com"));
+        assert!(!logs.contains("s3cr3t"));
+        assert!(logs.contains(r#""subscriber_email":"[REDACTED]""#));
+        assert!(logs.contains(r#""subscriber_lists":"newsletter""#));
+    }
+
+    #[test]
+    fn sensitive_span_fields_can_be_hashed_for_correlation() {
+        let hashed_email = |logs: String| {
+            let line: serde_json::Value =
+                serde_json::from_str(logs.lines().next().unwrap()).unwrap();
+            line["subscriber_email"].as_str().unwrap().to_string()
+        };
+        let logs = log_signup(RedactionMode::Hash);
+
+        assert!(!logs.contains("ursula_le_guin@gmail.com"));
+        let hashed = hashed_email(logs);
+        assert!(hashed.starts_with("sha256:"));
+        assert_eq!(hashed, hashed_email(log_signup(RedactionMode::Hash)));
+    }
+
+    #[test]
+    fn full_values_are_logged_when_redaction_is_off() {
+        let logs = log_signup(RedactionMode::Off);
+
+        assert!(logs.contains("ursula_le_guin@gmail.com"));
+    }
+
+    #[test]
+    fn sensitive_event_fields_are_redacted() {
+        let logs = log_signup(RedactionMode::Redact);
+
+        let event = logs
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .find(|line| line["msg"].as_str().unwrap().ends_with("Subscriber stored"))
+            .unwrap();
+        assert_eq!(event["confirmation_email"], "[REDACTED]");
+    }
+
+    #[test]
+    fn sensitive_values_are_masked_in_messages() {
+        let logs = CapturedLogs::default();
+        let subscriber = subscriber(&logs, unexported_tracer(), RedactionMode::Redact);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let email = "ursula_le_guin@gmail.com";
+            tracing::info!(subscriber_email = %email, "Stored {}", email);
+        });
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(!logs.contains("ursula_le_guin@gmail.com"));
+        assert!(logs.contains(r#""msg":"Stored [REDACTED]""#));
+    }
+
+    #[test]
+    fn structured_values_of_sensitive_fields_are_masked_whole() {
+        let logs = CapturedLogs::default();
+        let subscriber = subscriber(&logs, unexported_tracer(), RedactionMode::Redact);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let emails = vec!["ursula_le_guin@gmail.com", "terry@discworld.com"];
+            tracing::info!(subscriber_email = ?emails, "Subscribers stored");
+        });
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(!logs.contains("@"));
+        assert!(logs.contains(r#""subscriber_email":"[REDACTED]""#));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spans_are_exported_to_the_otlp_collector_with_sensitive_fields_redacted() {
+        let collector = MockServer::start().await;
+        Mock::given(path("/v1/traces"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1..)
+            .mount(&collector)
+            .await;
+
+        let tracer = get_tracer(&TelemetrySettings {
+            service_name: String::from("test"),
+            log_level: String::from("info"),
+            otlp_endpoint: Some(collector.uri()),
+            redaction: redaction(RedactionMode::Redact),
+        })
+        .unwrap();
+        let provider = tracer.provider().unwrap();
+        let subscriber = subscriber(&CapturedLogs::default(), tracer, RedactionMode::Redact);
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!(
+                "Adding a new subscriber",
+                subscriber_email = "ursula_le_guin@gmail.com",
+                subscriber_lists = "newsletter",
+            )
+            .in_scope(|| {
+                tracing::info!(
+                    confirmation_email = "ursula_le_guin@gmail.com",
+                    "Subscriber stored"
+                )
+            });
+        });
+
+        tokio::task::spawn_blocking(move || provider.force_flush())
+            .await
+            .unwrap()
+            .into_iter()
+            .for_each(|result| result.unwrap());
+
+        let requests = collector.received_requests().await.unwrap();
+        let span = requests
+            .iter()
+            .map(|request| ExportTraceServiceRequest::decode(request.body.as_slice()).unwrap())
+            .flat_map(|export| export.resource_spans)
+            .flat_map(|resource| resource.scope_spans)
+            .flat_map(|scope| scope.spans)
+            .find(|span| span.name == "Adding a new subscriber")
+            .unwrap();
+        let attributes = span
+            .attributes
+            .iter()
+            .chain(span.events.iter().flat_map(|event| &event.attributes));
+        let string_value = |key: &str| {
+            attributes
+                .clone()
+                .find(|attribute| attribute.key == key)
+                .and_then(|attribute| attribute.value.clone()?.value)
+                .map(|value| match value {
+                    any_value::Value::StringValue(value) => value,
+                    value => panic!("Unexpected value: {value:?}"),
+                })
+                .unwrap()
+        };
+        assert_eq!(string_value("subscriber_email"), "[REDACTED]");
+        assert_eq!(string_value("confirmation_email"), "[REDACTED]");
+        assert_eq!(string_value("subscriber_lists"), "newsletter");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn query_strings_are_left_out_of_request_spans() {
+        let collector = MockServer::start().await;
+        Mock::given(path("/v1/traces"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&collector)
+            .await;
+        let tracer = get_tracer(&TelemetrySettings {
+            service_name: String::from("test"),
+            log_level: String::from("info"),
+            otlp_endpoint: Some(collector.uri()),
+            redaction: redaction(RedactionMode::Redact),
+        })
+        .unwrap();
+        let provider = tracer.provider().unwrap();
+        let logs = CapturedLogs::default();
+        let subscriber = subscriber(&logs, tracer, RedactionMode::Redact);
+
+        {
+            let _default = tracing::subscriber::set_default(subscriber);
+            let app = actix_test::init_service(
+                App::new()
+                    .wrap(TracingLogger::<RequestRootSpanBuilder>::new())
+                    .route("/subscriptions/confirm", web::get().to(HttpResponse::Ok)),
+            )
+            .await;
+            let request = actix_test::TestRequest::get()
+                .uri("/subscriptions/confirm?subscription_token=s3cr3t")
+                .to_request();
+            actix_test::call_service(&app, request).await;
+        }
+        tokio::task::spawn_blocking(move || provider.force_flush())
+            .await
+            .unwrap()
+            .into_iter()
+            .for_each(|result| result.unwrap());
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains(r#""http.target":"/subscriptions/confirm""#));
+        assert!(!logs.contains("s3cr3t"));
+        let span = collector
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|request| ExportTraceServiceRequest::decode(request.body.as_slice()).unwrap())
+            .flat_map(|export| export.resource_spans)
+            .flat_map(|resource| resource.scope_spans)
+            .flat_map(|scope| scope.spans)
+            .find(|span| span.name == "HTTP GET /subscriptions/confirm")
+            .unwrap();
+        assert!(!format!("{:?}", span.attributes).contains("s3cr3t"));
+    }
+}
@@ -0,0 +1,61 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn create_list_returns_201_and_persists_list() {
+    let app = spawn_app().await;
+
+    let resp = app.create_list("rust-weekly").await;
+    assert_eq!(resp.status().as_u16(), 201);
+
+    let saved = sqlx::query!("SELECT slug FROM lists WHERE slug = 'rust-weekly'")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch created list");
+    assert_eq!(saved.slug, "rust-weekly");
+}
+
+#[tokio::test]
+async fn create_list_with_existing_slug_returns_409() {
+    let app = spawn_app().await;
+
+    app.create_list("rust-weekly")
+        .await
+        .error_for_status()
+        .unwrap();
+    let resp = app.create_list("rust-weekly").await;
+
+    assert_eq!(resp.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn create_list_with_invalid_slug_returns_400() {
+    let app = spawn_app().await;
+
+    for slug in ["", "Rust Weekly", "rust/weekly"] {
+        let resp = app.create_list(slug).await;
+        assert_eq!(
+            resp.status().as_u16(),
+            400,
+            "API did not reject list slug {:?}",
+            slug
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_default_list_exists() {
+    let app = spawn_app().await;
+
+    let lists: Vec<serde_json::Value> = reqwest::Client::new()
+        .get(format!("{}/admin/lists", app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(lists.len(), 1);
+    assert_eq!(lists[0]["slug"], "newsletter");
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use crate::helpers::spawn_app;
+
+// The log filter is process-wide, so every change to it happens in this one
+// test rather than in tests that could run concurrently.
+#[tokio::test]
+async fn log_level_can_be_changed_temporarily() {
+    let app = spawn_app().await;
+    let res = app.get_log_level().await;
+    assert_eq!(res.status().as_u16(), 200);
+    let initial = res.json::<serde_json::Value>().await.unwrap()["directive"].clone();
+
+    let res = app
+        .put_log_level(serde_json::json!({
+            "directive": "info,zero2prod=trace",
+            "ttl_seconds": 1,
+        }))
+        .await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let current = app
+        .get_log_level()
+        .await
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_ne!(current["directive"], initial);
+    assert!(current["directive"]
+        .as_str()
+        .unwrap()
+        .contains("zero2prod=trace"));
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let current = app
+        .get_log_level()
+        .await
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(current["directive"], initial);
+}
+
+#[tokio::test]
+async fn invalid_directives_are_rejected() {
+    let app = spawn_app().await;
+
+    for body in [
+        serde_json::json!({ "directive": "zero2prod=loud" }),
+        serde_json::json!({ "directive": "info", "ttl_seconds": 0 }),
+    ] {
+        let res = app.put_log_level(body.clone()).await;
+        assert_eq!(res.status().as_u16(), 400, "{} was accepted", body);
+    }
+}
+
+#[tokio::test]
+async fn changing_the_log_level_requires_authentication() {
+    let app = spawn_app().await;
+
+    let res = reqwest::Client::new()
+        .put(format!("{}/admin/log-level", app.address))
+        .json(&serde_json::json!({ "directive": "trace" }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(res.status().as_u16(), 401);
+}
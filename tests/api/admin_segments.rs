@@ -0,0 +1,50 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn create_segment_returns_201_for_a_valid_expression() {
+    let app = spawn_app().await;
+
+    let resp = app
+        .create_segment(
+            "germans",
+            r#"country = "DE" and signed_up_after 2025-01-01"#,
+        )
+        .await;
+
+    assert_eq!(resp.status().as_u16(), 201);
+    let saved = sqlx::query!("SELECT name, expression FROM segments")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch created segment");
+    assert_eq!(saved.name, "germans");
+    assert_eq!(
+        saved.expression,
+        r#"country = "DE" and signed_up_after 2025-01-01"#
+    );
+}
+
+#[tokio::test]
+async fn create_segment_returns_400_with_the_parse_error() {
+    let app = spawn_app().await;
+
+    let resp = app.create_segment("broken", r#"country = "DE" and"#).await;
+
+    assert_eq!(resp.status().as_u16(), 400);
+    assert_eq!(
+        resp.text().await.unwrap(),
+        "Unexpected end of segment expression"
+    );
+}
+
+#[tokio::test]
+async fn create_segment_with_existing_name_returns_409() {
+    let app = spawn_app().await;
+
+    app.create_segment("germans", r#"country = "DE""#)
+        .await
+        .error_for_status()
+        .unwrap();
+    let resp = app.create_segment("germans", r#"country = "AT""#).await;
+
+    assert_eq!(resp.status().as_u16(), 409);
+}
@@ -0,0 +1,234 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use zero2prod::configuration::{ReplicaSettings, Settings};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn create_subscribers(app: &TestApp) {
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula@earthsea.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    app.post_subscriptions("name=pratchett&email=terry@discworld.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn admin_endpoints_reject_requests_without_credentials() {
+    let app = spawn_app().await;
+
+    for endpoint in ["admin/subscribers", "admin/subscribers/export?format=csv"] {
+        let resp = reqwest::get(format!("{}/{}", app.address, endpoint))
+            .await
+            .expect("Failed to execute request.");
+
+        assert_eq!(
+            resp.status().as_u16(),
+            401,
+            "{} was not protected",
+            endpoint
+        );
+        assert_eq!(resp.headers()["WWW-Authenticate"], r#"Basic realm="admin""#);
+    }
+}
+
+#[tokio::test]
+async fn admin_endpoints_reject_invalid_password() {
+    let app = spawn_app().await;
+
+    let resp = reqwest::Client::new()
+        .get(format!(
+            "{}/admin/subscribers/export?format=csv",
+            app.address
+        ))
+        .basic_auth(&app.test_user.username, Some("not-the-password"))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(resp.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn list_subscribers_applies_filters() {
+    let app = spawn_app().await;
+    create_subscribers(&app).await;
+
+    let all: Vec<serde_json::Value> = app.get_admin_subscribers("").await.json().await.unwrap();
+    assert_eq!(all.len(), 2);
+
+    let confirmed: Vec<serde_json::Value> = app
+        .get_admin_subscribers("status=confirmed")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(confirmed.len(), 1);
+    assert_eq!(confirmed[0]["email"], "ursula@earthsea.com");
+}
+
+fn with_replica_on_port(port: Option<u16>) -> impl FnOnce(&mut Settings) {
+    move |c| {
+        c.database.replica = Some(ReplicaSettings {
+            host: c.database.host.clone(),
+            port: port.unwrap_or(c.database.port),
+            health_check_interval_ms: 1000,
+        })
+    }
+}
+
+#[tokio::test]
+async fn subscribers_are_listed_from_a_healthy_replica() {
+    let app = spawn_app_with(with_replica_on_port(None)).await;
+    create_subscribers(&app).await;
+
+    let all: Vec<serde_json::Value> = app.get_admin_subscribers("").await.json().await.unwrap();
+    assert_eq!(all.len(), 2);
+}
+
+#[tokio::test]
+async fn subscribers_are_listed_from_the_primary_while_the_replica_is_down() {
+    let app = spawn_app_with(with_replica_on_port(Some(1))).await;
+    create_subscribers(&app).await;
+
+    let resp = app.get_admin_subscribers("").await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let all: Vec<serde_json::Value> = resp.json().await.unwrap();
+    assert_eq!(all.len(), 2);
+
+    let resp = app.export_subscribers("format=csv").await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.text().await.unwrap().lines().count(), 3);
+}
+
+#[tokio::test]
+async fn export_subscribers_as_csv() {
+    let app = spawn_app().await;
+    create_subscribers(&app).await;
+
+    let resp = app.export_subscribers("format=csv").await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.headers()["Content-Type"], "text/csv; charset=utf-8");
+
+    let body = resp.text().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "id,email,name,subscribed_at,status");
+    assert!(lines[1].contains("ursula@earthsea.com,le guin,"));
+    assert!(lines[1].ends_with(",confirmed"));
+    assert!(lines[2].ends_with(",pending_confirmation"));
+}
+
+#[tokio::test]
+async fn export_subscribers_as_json_lines_with_filters() {
+    let app = spawn_app().await;
+    create_subscribers(&app).await;
+
+    let resp = app
+        .export_subscribers("format=jsonl&status=pending_confirmation")
+        .await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let body = resp.text().await.unwrap();
+    let records: Vec<serde_json::Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["email"], "terry@discworld.com");
+    assert_eq!(records[0]["status"], "pending_confirmation");
+}
+
+#[tokio::test]
+async fn export_subscribers_rejects_unknown_format() {
+    let app = spawn_app().await;
+
+    let resp = app.export_subscribers("format=xml").await;
+
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn put_subscriber_attributes_replaces_attributes() {
+    let app = spawn_app().await;
+    create_subscribers(&app).await;
+    let subscriber =
+        sqlx::query!("SELECT id FROM subscriptions WHERE email = 'terry@discworld.com'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+
+    let resp = app
+        .put_subscriber_attributes(
+            &subscriber.id.to_string(),
+            serde_json::json!({ "country": "GB", "plan": "pro" }),
+        )
+        .await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let saved = sqlx::query!(
+        "SELECT attributes FROM subscriptions WHERE id = $1",
+        subscriber.id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        saved.attributes,
+        serde_json::json!({ "country": "GB", "plan": "pro" })
+    );
+}
+
+#[tokio::test]
+async fn put_subscriber_attributes_rejects_invalid_attributes() {
+    let app = spawn_app().await;
+    create_subscribers(&app).await;
+    let subscriber = sqlx::query!("SELECT id FROM subscriptions LIMIT 1")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    let resp = app
+        .put_subscriber_attributes(
+            &subscriber.id.to_string(),
+            serde_json::json!({ "Country Code": "GB" }),
+        )
+        .await;
+
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn put_subscriber_attributes_for_unknown_subscriber_returns_404() {
+    let app = spawn_app().await;
+
+    let resp = app
+        .put_subscriber_attributes(
+            &uuid::Uuid::new_v4().to_string(),
+            serde_json::json!({ "country": "GB" }),
+        )
+        .await;
+
+    assert_eq!(resp.status().as_u16(), 404);
+}
@@ -0,0 +1,180 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn subscribing_with_json_returns_202_and_persists_the_subscriber() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let resp = app
+        .post_api_subscriptions(serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "attributes": { "plan": "pro" },
+        }))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(resp.status().as_u16(), 202);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["status"], "pending_confirmation");
+
+    let saved = sqlx::query!("SELECT name, email, status, attributes FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.name, "le guin");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.status, "pending_confirmation");
+    assert_eq!(saved.attributes, serde_json::json!({ "plan": "pro" }));
+}
+
+#[tokio::test]
+async fn every_invalid_field_is_reported_as_problem_details() {
+    let app = spawn_app().await;
+
+    let resp = app
+        .post_api_subscriptions(serde_json::json!({
+            "name": "",
+            "email": "not-an-email",
+            "lists": [],
+        }))
+        .await;
+
+    assert_eq!(resp.status().as_u16(), 400);
+    assert_eq!(resp.headers()["content-type"], "application/problem+json");
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["type"], "about:blank");
+    assert_eq!(body["title"], "Bad Request");
+    assert_eq!(body["status"], 400);
+    assert!(body["request_id"].is_string());
+    let invalid_params = body["invalid-params"].as_array().unwrap();
+    let names: Vec<_> = invalid_params.iter().map(|p| &p["name"]).collect();
+    assert_eq!(names, ["name", "email", "lists"]);
+    assert_eq!(
+        invalid_params[1]["reason"],
+        "not-an-email is an invalid email"
+    );
+}
+
+#[tokio::test]
+async fn missing_fields_are_reported_as_invalid() {
+    let app = spawn_app().await;
+
+    let resp = app
+        .post_api_subscriptions(serde_json::json!({ "name": "le guin" }))
+        .await;
+
+    assert_eq!(resp.status().as_u16(), 400);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["invalid-params"][0]["name"], "email");
+}
+
+#[tokio::test]
+async fn fields_of_the_wrong_type_are_reported_as_invalid() {
+    let app = spawn_app().await;
+
+    let resp = app
+        .post_api_subscriptions(serde_json::json!({
+            "name": null,
+            "email": 42,
+            "lists": "newsletter",
+            "attributes": { "age": 30 },
+        }))
+        .await;
+
+    assert_eq!(resp.status().as_u16(), 400);
+    assert_eq!(resp.headers()["content-type"], "application/problem+json");
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["detail"], "The request has invalid parameters");
+    let invalid_params = body["invalid-params"].as_array().unwrap();
+    let names: Vec<_> = invalid_params.iter().map(|p| &p["name"]).collect();
+    assert_eq!(names, ["name", "email", "lists", "attributes"]);
+    assert_eq!(invalid_params[1]["reason"], "Must be a string");
+    assert_eq!(
+        invalid_params[3]["reason"],
+        "The value of attribute age must be a string"
+    );
+}
+
+#[tokio::test]
+async fn resubmitting_a_confirmed_email_is_reported_on_the_email_field() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let request = serde_json::json!({
+        "name": "le guin",
+        "email": "ursula_le_guin@gmail.com",
+    });
+    app.post_api_subscriptions(request.clone())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let resp = app.post_api_subscriptions(request).await;
+
+    assert_eq!(resp.status().as_u16(), 409);
+    assert_eq!(resp.headers()["content-type"], "application/problem+json");
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["status"], 409);
+    assert_eq!(body["invalid-params"][0]["name"], "email");
+}
+
+#[tokio::test]
+async fn unknown_lists_are_reported_as_invalid() {
+    let app = spawn_app().await;
+
+    let resp = app
+        .post_api_subscriptions(serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "lists": ["does-not-exist"],
+        }))
+        .await;
+
+    assert_eq!(resp.status().as_u16(), 400);
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["invalid-params"][0]["name"], "lists");
+}
+
+#[tokio::test]
+async fn malformed_json_is_rejected_with_problem_details() {
+    let app = spawn_app().await;
+
+    let resp = reqwest::Client::new()
+        .post(format!("{}/api/v1/subscriptions", app.address))
+        .header("Content-Type", "application/json")
+        .body("{\"name\": ")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(resp.status().as_u16(), 400);
+    assert_eq!(resp.headers()["content-type"], "application/problem+json");
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["status"], 400);
+    assert!(body["detail"].as_str().unwrap().starts_with("Json"));
+}
@@ -0,0 +1,45 @@
+use claims::assert_err;
+use secrecy::Secret;
+use zero2prod::{authentication::create_user, startup::pending_migrations};
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn no_migration_is_pending_once_the_database_is_migrated() {
+    let app = spawn_app().await;
+
+    let pending = pending_migrations(&app.db_pool).await.unwrap();
+
+    assert!(pending.is_empty());
+}
+
+#[tokio::test]
+async fn created_admins_can_use_the_admin_endpoints() {
+    let app = spawn_app().await;
+
+    create_user("ops", Secret::new(String::from("hunter22")), &app.db_pool)
+        .await
+        .unwrap();
+
+    let resp = reqwest::Client::new()
+        .get(format!("{}/admin/subscribers", app.address))
+        .basic_auth("ops", Some("hunter22"))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(resp.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn admin_usernames_are_unique() {
+    let app = spawn_app().await;
+
+    let result = create_user(
+        &app.test_user.username,
+        Secret::new(String::from("hunter22")),
+        &app.db_pool,
+    )
+    .await;
+
+    assert_err!(result);
+}
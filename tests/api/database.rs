@@ -0,0 +1,44 @@
+use zero2prod::{configuration::get_config, startup::get_migration_pool};
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn connections_are_named_and_set_a_statement_timeout() {
+    let app = spawn_app().await;
+
+    let (application_name, statement_timeout): (String, String) = sqlx::query_as(
+        "SELECT current_setting('application_name'), current_setting('statement_timeout')",
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+
+    assert_eq!(application_name, "zero2prod");
+    assert_eq!(statement_timeout, "30s");
+}
+
+#[tokio::test]
+async fn migrations_run_without_a_statement_timeout() {
+    let app = spawn_app().await;
+    let mut config = get_config().unwrap().database;
+    config.database_name = sqlx::query_scalar("SELECT current_database()")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query(&format!(
+        r#"ALTER DATABASE "{}" SET statement_timeout = '1ms'"#,
+        config.database_name
+    ))
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let migration_pool = get_migration_pool(&config).await.unwrap();
+
+    let statement_timeout: String =
+        sqlx::query_scalar("SELECT current_setting('statement_timeout')")
+            .fetch_one(&migration_pool)
+            .await
+            .unwrap();
+    assert_eq!(statement_timeout, "0");
+}
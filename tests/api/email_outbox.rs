@@ -0,0 +1,150 @@
+use std::time::Duration;
+
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::email_outbox::{try_dispatch_next, DispatchOutcome};
+
+use crate::helpers::{spawn_app, spawn_app_with};
+
+#[tokio::test]
+async fn subscribing_does_not_depend_on_the_email_api() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let resp = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "pending_confirmation");
+
+    let queued = sqlx::query!("SELECT attempts, next_attempt_at, last_error FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("The confirmation email is no longer queued");
+    assert_eq!(queued.attempts, 1);
+    assert!(queued.next_attempt_at.unwrap() > chrono::Utc::now());
+    assert!(queued.last_error.is_some());
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried() {
+    let app = spawn_app_with(|c| c.outbox.retry_backoff_ms = 0).await;
+
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!("SELECT COUNT(*) AS count FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, Some(0));
+}
+
+#[tokio::test]
+async fn deliveries_are_given_up_after_the_max_attempts() {
+    let app = spawn_app_with(|c| {
+        c.outbox.retry_backoff_ms = 0;
+        c.outbox.max_attempts = 3;
+    })
+    .await;
+
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let queued = sqlx::query!("SELECT attempts, next_attempt_at FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Abandoned emails are kept in the outbox");
+    assert_eq!(queued.attempts, 3);
+    assert_eq!(queued.next_attempt_at, None);
+}
+
+#[tokio::test]
+async fn emails_being_delivered_are_skipped_until_their_lease_expires() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // The delivery holds no transaction open, only a lease on the entry
+    let (pool, email_client, outbox) = (
+        app.db_pool.clone(),
+        app.email_client.clone(),
+        app.outbox.clone(),
+    );
+    let delivery = tokio::spawn(async move {
+        try_dispatch_next(&pool, &email_client, &outbox)
+            .await
+            .unwrap()
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let locked_until = sqlx::query!("SELECT locked_until FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .locked_until;
+    assert!(locked_until.unwrap() > chrono::Utc::now());
+    assert!(matches!(
+        try_dispatch_next(&app.db_pool, &app.email_client, &app.outbox)
+            .await
+            .unwrap(),
+        DispatchOutcome::EmptyQueue
+    ));
+
+    // A dispatcher dying mid-attempt leaves the lease to lapse
+    sqlx::query!("UPDATE email_outbox SET locked_until = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(matches!(
+        try_dispatch_next(&app.db_pool, &app.email_client, &app.outbox)
+            .await
+            .unwrap(),
+        DispatchOutcome::Attempted
+    ));
+    delivery.await.unwrap();
+}
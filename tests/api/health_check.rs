@@ -1,4 +1,6 @@
-use crate::helpers::spawn_app;
+use wiremock::{matchers::any, Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn health_check_works() {
@@ -15,3 +17,126 @@ async fn health_check_works() {
     assert!(res.status().is_success());
     assert_eq!(res.content_length(), Some(0));
 }
+
+#[tokio::test]
+async fn liveness_check_works() {
+    let app = spawn_app().await;
+
+    let res = reqwest::get(format!("{}/health/live", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(res.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn readiness_reports_each_check() {
+    let app = spawn_app().await;
+
+    let res = reqwest::get(format!("{}/health/ready", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(res.status().as_u16(), 200);
+    let report: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(report["status"], "ok");
+    let checks = report["checks"].as_array().unwrap();
+    assert_eq!(checks.len(), 2);
+    for (check, name) in checks.iter().zip(["database", "migrations"]) {
+        assert_eq!(check["name"], name);
+        assert_eq!(check["status"], "ok");
+        assert!(check["latency_ms"].is_u64());
+    }
+}
+
+#[tokio::test]
+async fn readiness_fails_when_database_is_unreachable() {
+    let app = spawn_app_with(|c| c.database.port = 1).await;
+
+    let res = reqwest::get(format!("{}/health/ready", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(res.status().as_u16(), 503);
+    let report: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(report["status"], "fail");
+    assert_eq!(report["checks"][0]["name"], "database");
+    assert_eq!(report["checks"][0]["status"], "fail");
+    assert!(report["checks"][0]["error"].is_string());
+}
+
+#[tokio::test]
+async fn readiness_fails_when_migrations_are_missing() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        "DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let res = reqwest::get(format!("{}/health/ready", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(res.status().as_u16(), 503);
+    let report: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(report["checks"][0]["status"], "ok");
+    assert_eq!(report["checks"][1]["name"], "migrations");
+    assert_eq!(report["checks"][1]["status"], "fail");
+}
+
+#[tokio::test]
+async fn readiness_accepts_migrations_from_a_newer_release() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+        VALUES (99990101000000, 'from a newer release', true, '\\x00', 0)"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let res = reqwest::get(format!("{}/health/ready", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(res.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn readiness_probes_email_api_when_enabled() {
+    let app = spawn_app_with(|c| c.health.check_email_api = true).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let res = reqwest::get(format!("{}/health/ready", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(res.status().as_u16(), 200);
+    let report: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(report["checks"][2]["name"], "email_api");
+    assert_eq!(report["checks"][2]["status"], "ok");
+}
+
+#[tokio::test]
+async fn readiness_fails_when_email_api_is_unreachable() {
+    let app = spawn_app_with(|c| {
+        c.health.check_email_api = true;
+        c.email_client.base_url = String::from("http://127.0.0.1:1");
+    })
+    .await;
+
+    let res = reqwest::get(format!("{}/health/ready", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(res.status().as_u16(), 503);
+    let report: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(report["checks"][2]["status"], "fail");
+}
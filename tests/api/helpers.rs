@@ -1,24 +1,51 @@
+use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
 use once_cell::sync::Lazy;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use tokio::task::JoinHandle;
 use uuid::Uuid;
 use wiremock::MockServer;
 use zero2prod::{
-    configuration::{get_config, DatabaseSettings},
-    startup::{get_connection_pool, Application},
-    telemetry::{get_subscriber, init_subscriber},
+    configuration::{get_config, DatabaseSettings, OutboxSettings, Settings, TelemetrySettings},
+    email_client::EmailClient,
+    email_outbox::{try_dispatch_next, DispatchOutcome},
+    listener::ListenAddress,
+    shutdown::Shutdown,
+    startup::{get_connection_pool, get_migration_pool, Application},
+    telemetry::{get_subscriber, get_tracer, init_subscriber, LogFilter},
 };
 
 pub struct TestApp {
     pub address: String,
+    pub listen_address: ListenAddress,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    pub email_client: EmailClient,
+    pub outbox: OutboxSettings,
     pub port: u16,
+    pub metrics_port: Option<u16>,
+    pub test_user: TestUser,
+    pub shutdown: Shutdown,
+    pub server: JoinHandle<Result<(), std::io::Error>>,
 }
 
 impl TestApp {
+    /// Delivers the emails queued in the outbox, standing in for the
+    /// dispatcher that runs next to the app.
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let DispatchOutcome::EmptyQueue =
+                try_dispatch_next(&self.db_pool, &self.email_client, &self.outbox)
+                    .await
+                    .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscribe", self.address))
+            .post(format!("{}/subscribe", self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -26,25 +53,142 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_api_subscriptions(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/api/v1/subscriptions", self.address))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_subscribers(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/subscribers?{}", self.address, query))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn export_subscribers(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/admin/subscribers/export?{}",
+                self.address, query
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_metrics(&self) -> reqwest::Response {
+        let address = match self.metrics_port {
+            Some(port) => format!("http://127.0.0.1:{}", port),
+            None => self.address.clone(),
+        };
+        reqwest::Client::new()
+            .get(format!("{}/metrics", address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn create_list(&self, slug: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/lists", self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&serde_json::json!({ "slug": slug, "name": slug }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn create_segment(&self, name: &str, expression: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/segments", self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&serde_json::json!({ "name": name, "expression": expression }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_subscriber_attributes(
+        &self,
+        subscriber_id: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!(
+                "{}/admin/subscribers/{}/attributes",
+                self.address, subscriber_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/newsletters", self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_log_level(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/log-level", self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_log_level(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/admin/log-level", self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+        self.get_links(email_request, "/subscriptions/confirm")
+    }
+
+    pub fn get_preferences_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+        self.get_links(email_request, "/subscriptions/preferences")
+    }
+
+    fn get_links(&self, email_request: &wiremock::Request, path: &str) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
         let get_link = |s: &str| {
             let links: Vec<_> = linkify::LinkFinder::new()
                 .links(s)
                 .filter(|l| *l.kind() == linkify::LinkKind::Url)
+                .map(|l| reqwest::Url::parse(l.as_str()).unwrap())
+                .filter(|l| l.path() == path)
                 .collect();
             assert_eq!(links.len(), 1);
 
-            let raw_link = links[0].as_str().to_owned();
-            let mut link = reqwest::Url::parse(&raw_link).unwrap();
+            let mut link = links[0].clone();
             assert_eq!(link.host_str().unwrap(), "127.0.0.1");
 
             link.set_port(Some(self.port)).unwrap();
             link
         };
 
-        let html_link = get_link(&body["Messages"][0]["HTMLPart"].as_str().unwrap());
-        let plain_text = get_link(&body["Messages"][0]["TextPart"].as_str().unwrap());
+        let html_link = get_link(body["Messages"][0]["HTMLPart"].as_str().unwrap());
+        let plain_text = get_link(body["Messages"][0]["TextPart"].as_str().unwrap());
 
         ConfirmationLinks {
             html: html_link,
@@ -53,20 +197,46 @@ impl TestApp {
     }
 }
 
-static TRACING: Lazy<()> = Lazy::new(|| {
+static TRACING: Lazy<LogFilter> = Lazy::new(|| {
     let default_tracing_level = String::from("debug");
     let subscriber_name = String::from("test");
+    let telemetry = get_config().expect("Failed to read config").telemetry;
+    let tracer = get_tracer(&TelemetrySettings {
+        otlp_endpoint: None,
+        ..telemetry.clone()
+    })
+    .expect("Failed to build tracer");
 
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_tracing_level, std::io::stdout);
+        let (subscriber, log_filter) = get_subscriber(
+            subscriber_name,
+            default_tracing_level,
+            std::io::stdout,
+            tracer,
+            telemetry.redaction,
+        );
         init_subscriber(subscriber);
+        log_filter
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_tracing_level, std::io::sink);
+        let (subscriber, log_filter) = get_subscriber(
+            subscriber_name,
+            default_tracing_level,
+            std::io::sink,
+            tracer,
+            telemetry.redaction,
+        );
         init_subscriber(subscriber);
+        log_filter
     }
 });
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawns the app after letting `configure` tweak its settings. The test
+/// database is set up from the settings as they were before the tweak.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
@@ -81,19 +251,40 @@ pub async fn spawn_app() -> TestApp {
 
     configure_db(&config.database).await;
 
-    let application = Application::build(config.clone())
+    let mut app_config = config.clone();
+    configure(&mut app_config);
+    let email_client = app_config.email_client.clone().client();
+    let outbox = app_config.outbox.clone();
+    let scheme = match app_config.application.tls {
+        Some(_) => "https",
+        None => "http",
+    };
+
+    let application = Application::build(app_config, TRACING.clone(), None)
         .await
         .expect("Failed to build app");
-    let application_port = application.port();
-    let address = format!("http://127.0.0.1:{}", application_port);
-    let _ = tokio::spawn(application.run_until_stopped());
+    let listen_address = application.address().clone();
+    let application_port = listen_address.port().unwrap_or_default();
+    let metrics_port = application.metrics_port();
+    let address = format!("{}://127.0.0.1:{}", scheme, application_port);
+    let shutdown = application.shutdown();
+    let server = tokio::spawn(application.run_until_stopped());
 
-    TestApp {
+    let test_app = TestApp {
         address,
+        listen_address,
         db_pool: get_connection_pool(&config.database),
         email_server,
+        email_client,
+        outbox,
         port: application_port,
-    }
+        metrics_port,
+        test_user: TestUser::generate(),
+        shutdown,
+        server,
+    };
+    test_app.test_user.store(&test_app.db_pool).await;
+    test_app
 }
 
 async fn configure_db(config: &DatabaseSettings) -> PgPool {
@@ -104,19 +295,57 @@ async fn configure_db(config: &DatabaseSettings) -> PgPool {
         .await
         .expect("Failed to create test DB");
 
-    let connection_pool = PgPool::connect_with(config.with_db())
+    let migration_pool = get_migration_pool(config)
         .await
         .expect("Failed to connect to test db");
 
     sqlx::migrate!("./migrations")
-        .run(&connection_pool)
+        .run(&migration_pool)
         .await
         .expect("Failed to run migration on test DB");
 
-    connection_pool
+    migration_pool
 }
 
 pub struct ConfirmationLinks {
     pub html: reqwest::Url,
     pub plain_text: reqwest::Url,
 }
+
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(15000, 2, 1, None).unwrap(),
+        )
+        .hash_password(self.password.as_bytes(), &salt)
+        .unwrap()
+        .to_string();
+
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)",
+            self.user_id,
+            self.username,
+            password_hash,
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
+    }
+}
@@ -1,4 +1,20 @@
+mod admin_lists;
+mod admin_log_level;
+mod admin_segments;
+mod admin_subscribers;
+mod api_subscriptions;
+mod cli;
+mod database;
+mod email_outbox;
 mod health_check;
 mod helpers;
+mod metrics;
+mod newsletters;
+mod request_id;
+mod security_headers;
+mod server;
+mod shutdown;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_email;
+mod subscriptions_preferences;
@@ -0,0 +1,92 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, spawn_app_with};
+
+#[tokio::test]
+async fn metrics_are_exposed_in_prometheus_format() {
+    let app = spawn_app().await;
+
+    let res = app.get_metrics().await;
+
+    assert_eq!(res.status().as_u16(), 200);
+    assert!(res.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    let body = res.text().await.unwrap();
+    for metric in [
+        "db_pool_connections",
+        "db_pool_idle_connections",
+        "db_pool_acquire_wait_seconds",
+        "subscriptions_created_total",
+        "confirmations_completed_total",
+    ] {
+        assert!(body.contains(metric), "{} is missing", metric);
+    }
+}
+
+#[tokio::test]
+async fn requests_are_counted_per_route() {
+    let app = spawn_app().await;
+    reqwest::get(format!("{}/health", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    let body = app.get_metrics().await.text().await.unwrap();
+
+    assert!(body.contains(r#"http_requests_total{method="GET",route="/health",status="200"}"#));
+    assert!(body.contains(r#"http_request_duration_seconds_bucket{method="GET",route="/health""#));
+}
+
+#[tokio::test]
+async fn connection_waits_are_recorded_when_opening_transactions() {
+    let app = spawn_app().await;
+    let count = |body: &str| -> u64 {
+        body.lines()
+            .find_map(|line| line.strip_prefix("db_pool_acquire_wait_seconds_count "))
+            .unwrap()
+            .parse()
+            .unwrap()
+    };
+
+    let before = count(&app.get_metrics().await.text().await.unwrap());
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let after = count(&app.get_metrics().await.text().await.unwrap());
+
+    assert!(after > before);
+}
+
+#[tokio::test]
+async fn email_sends_are_counted_by_outcome() {
+    let app = spawn_app().await;
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let body = app.get_metrics().await.text().await.unwrap();
+    assert!(body.contains(r#"emails_sent_total{outcome="success"}"#));
+    assert!(body.contains(r#"email_send_duration_seconds_count{outcome="success"}"#));
+}
+
+#[tokio::test]
+async fn metrics_can_be_served_on_a_separate_port() {
+    let app = spawn_app_with(|c| c.application.metrics_port = Some(0)).await;
+
+    let res = app.get_metrics().await;
+    assert_eq!(res.status().as_u16(), 200);
+
+    let res = reqwest::get(format!("{}/metrics", app.address))
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(res.status().as_u16(), 404);
+}
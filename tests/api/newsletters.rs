@@ -0,0 +1,277 @@
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+fn newsletter_request_body(list: &str) -> serde_json::Value {
+    serde_json::json!({
+        "list": list,
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+async fn create_unconfirmed_subscriber(app: &TestApp, body: &str) -> reqwest::Url {
+    let _mock_guard = Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(email_request).html
+}
+
+async fn create_confirmed_subscriber(app: &TestApp, body: &str) {
+    let confirmation_link = create_unconfirmed_subscriber(app, body).await;
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app, "name=le%20guin&email=ursula@earthsea.com").await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let resp = app
+        .post_newsletters(newsletter_request_body("newsletter"))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(resp.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers_of_the_target_list_only() {
+    let app = spawn_app().await;
+    app.create_list("daily").await.error_for_status().unwrap();
+    app.create_list("weekly").await.error_for_status().unwrap();
+    create_confirmed_subscriber(
+        &app,
+        "name=le%20guin&email=ursula@earthsea.com&lists=daily,weekly",
+    )
+    .await;
+    create_confirmed_subscriber(&app, "name=pratchett&email=terry@discworld.com&lists=daily").await;
+
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let resp = app
+        .post_newsletters(newsletter_request_body("weekly"))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(resp.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn newsletters_to_an_unknown_list_return_404() {
+    let app = spawn_app().await;
+
+    let resp = app
+        .post_newsletters(newsletter_request_body("does-not-exist"))
+        .await;
+
+    assert_eq!(resp.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_invalid_data() {
+    let app = spawn_app().await;
+
+    let scenarios = vec![
+        (
+            serde_json::json!({
+                "list": "newsletter",
+                "content": { "text": "Body", "html": "<p>Body</p>" }
+            }),
+            "missing title",
+        ),
+        (
+            serde_json::json!({ "list": "newsletter", "title": "Newsletter!" }),
+            "missing content",
+        ),
+        (
+            serde_json::json!({
+                "title": "Newsletter!",
+                "content": { "text": "Body", "html": "<p>Body</p>" }
+            }),
+            "missing list",
+        ),
+    ];
+
+    for (body, description) in scenarios {
+        let resp = app.post_newsletters(body).await;
+        assert_eq!(
+            resp.status().as_u16(),
+            400,
+            "API did not return 400 BAD REQUEST when payload was {}",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    let app = spawn_app().await;
+
+    let resp = reqwest::Client::new()
+        .post(format!("{}/admin/newsletters", app.address))
+        .json(&newsletter_request_body("newsletter"))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(resp.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn newsletters_respect_the_preferred_format_and_link_to_preferences() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app, "name=le%20guin&email=ursula@earthsea.com").await;
+    sqlx::query!("UPDATE subscriptions SET email_format = 'text'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body("newsletter"))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let message = &body["Messages"][0];
+    assert!(message.get("HTMLPart").is_none());
+    assert!(message["TextPart"]
+        .as_str()
+        .unwrap()
+        .contains("/subscriptions/preferences?token="));
+}
+
+#[tokio::test]
+async fn newsletters_can_target_a_named_segment() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(
+        &app,
+        "name=le%20guin&email=ursula@earthsea.com&attr_country=US",
+    )
+    .await;
+    create_confirmed_subscriber(
+        &app,
+        "name=funke&email=cornelia@tintenwelt.de&attr_country=DE",
+    )
+    .await;
+    app.create_segment(
+        "germans",
+        r#"country = "DE" and signed_up_after 2020-01-01"#,
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let mut body = newsletter_request_body("newsletter");
+    body["segment"] = "germans".into();
+    app.post_newsletters(body).await.error_for_status().unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(
+        body["Messages"][0]["To"][0]["Email"],
+        "cornelia@tintenwelt.de"
+    );
+}
+
+#[tokio::test]
+async fn newsletters_to_an_unknown_segment_return_404() {
+    let app = spawn_app().await;
+
+    let mut body = newsletter_request_body("newsletter");
+    body["segment"] = "does-not-exist".into();
+    let resp = app.post_newsletters(body).await;
+
+    assert_eq!(resp.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn newsletters_are_queued_for_every_recipient_before_responding() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app, "name=le%20guin&email=ursula@earthsea.com").await;
+    create_confirmed_subscriber(&app, "name=pratchett&email=terry@discworld.com").await;
+
+    let resp = app
+        .post_newsletters(newsletter_request_body("newsletter"))
+        .await;
+
+    assert_eq!(resp.status().as_u16(), 200);
+    let mut recipients: Vec<_> = sqlx::query!("SELECT recipient FROM email_outbox")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.recipient)
+        .collect();
+    recipients.sort();
+    assert_eq!(recipients, ["terry@discworld.com", "ursula@earthsea.com"]);
+    // Only the signup confirmations went out so far
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 2);
+}
@@ -0,0 +1,91 @@
+use wiremock::{
+    matchers::{header, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, spawn_app_with};
+
+#[tokio::test]
+async fn a_request_id_is_generated_when_none_is_sent() {
+    let app = spawn_app().await;
+
+    let res = reqwest::get(format!("{}/health", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    let request_id = res.headers()["X-Request-Id"].to_str().unwrap();
+    assert!(uuid::Uuid::parse_str(request_id).is_ok());
+}
+
+#[tokio::test]
+async fn an_upstream_request_id_is_echoed_back() {
+    let app = spawn_app().await;
+
+    let res = reqwest::Client::new()
+        .get(format!("{}/health", app.address))
+        .header("X-Request-Id", "lb-7f3a9c")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(res.headers()["X-Request-Id"], "lb-7f3a9c");
+}
+
+#[tokio::test]
+async fn an_invalid_upstream_request_id_is_replaced() {
+    let app = spawn_app().await;
+
+    let res = reqwest::Client::new()
+        .get(format!("{}/health", app.address))
+        .header("X-Request-Id", "not a valid id")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    let request_id = res.headers()["X-Request-Id"].to_str().unwrap();
+    assert!(uuid::Uuid::parse_str(request_id).is_ok());
+}
+
+#[tokio::test]
+async fn json_error_bodies_include_the_request_id() {
+    let app = spawn_app_with(|c| c.database.port = 1).await;
+
+    let res = reqwest::Client::new()
+        .get(format!("{}/health/ready", app.address))
+        .header("X-Request-Id", "lb-7f3a9c")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(res.status().as_u16(), 503);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["request_id"], "lb-7f3a9c");
+    assert_eq!(body["status"], "fail");
+}
+
+#[tokio::test]
+async fn the_request_id_is_forwarded_to_the_email_api() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .and(header("X-Request-Id", "lb-7f3a9c"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::Client::new()
+        .post(format!("{}/subscribe", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Request-Id", "lb-7f3a9c")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Messages"][0]["CustomID"], "lb-7f3a9c");
+}
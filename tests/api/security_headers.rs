@@ -0,0 +1,122 @@
+use crate::helpers::{spawn_app, spawn_app_with};
+
+const MARKETING_SITE: &str = "https://marketing.example.com";
+
+#[tokio::test]
+async fn responses_carry_security_headers() {
+    let app = spawn_app().await;
+
+    let resp = reqwest::get(format!("{}/health/live", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    let headers = resp.headers();
+    assert_eq!(
+        headers["content-security-policy"],
+        "default-src 'none'; frame-ancestors 'none'"
+    );
+    assert_eq!(headers["x-content-type-options"], "nosniff");
+    assert_eq!(headers["referrer-policy"], "no-referrer");
+    assert!(headers.get("strict-transport-security").is_none());
+}
+
+#[tokio::test]
+async fn hsts_is_sent_when_configured() {
+    let app =
+        spawn_app_with(|c| c.application.security_headers.hsts_max_age_secs = Some(600)).await;
+
+    let resp = reqwest::get(format!("{}/health/live", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(resp.headers()["strict-transport-security"], "max-age=600");
+}
+
+#[tokio::test]
+async fn allowed_origins_can_post_to_subscribe() {
+    let app = spawn_app_with(|c| {
+        c.application.cors.allowed_origins = vec![String::from(MARKETING_SITE)];
+    })
+    .await;
+    let client = reqwest::Client::new();
+
+    let preflight = client
+        .request(
+            reqwest::Method::OPTIONS,
+            format!("{}/subscribe", app.address),
+        )
+        .header("Origin", MARKETING_SITE)
+        .header("Access-Control-Request-Method", "POST")
+        .header("Access-Control-Request-Headers", "content-type")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(preflight.status().as_u16(), 200);
+    assert_eq!(
+        preflight.headers()["access-control-allow-origin"],
+        MARKETING_SITE
+    );
+    assert_eq!(preflight.headers()["access-control-max-age"], "3600");
+    assert!(preflight
+        .headers()
+        .get("access-control-allow-credentials")
+        .is_none());
+
+    let resp = client
+        .post(format!("{}/subscribe", app.address))
+        .header("Origin", MARKETING_SITE)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(
+        resp.headers()["access-control-allow-origin"],
+        MARKETING_SITE
+    );
+}
+
+#[tokio::test]
+async fn other_origins_are_not_allowed() {
+    let app = spawn_app_with(|c| {
+        c.application.cors.allowed_origins = vec![String::from(MARKETING_SITE)];
+    })
+    .await;
+
+    let resp = reqwest::Client::new()
+        .post(format!("{}/subscribe", app.address))
+        .header("Origin", "https://evil.example.com")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert!(resp.headers().get("access-control-allow-origin").is_none());
+}
+
+#[tokio::test]
+async fn credentials_are_allowed_when_configured() {
+    let app = spawn_app_with(|c| {
+        c.application.cors.allowed_origins = vec![String::from(MARKETING_SITE)];
+        c.application.cors.allow_credentials = true;
+    })
+    .await;
+
+    let preflight = reqwest::Client::new()
+        .request(
+            reqwest::Method::OPTIONS,
+            format!("{}/subscribe", app.address),
+        )
+        .header("Origin", MARKETING_SITE)
+        .header("Access-Control-Request-Method", "POST")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(
+        preflight.headers()["access-control-allow-credentials"],
+        "true"
+    );
+}
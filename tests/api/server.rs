@@ -0,0 +1,68 @@
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+};
+
+use uuid::Uuid;
+use zero2prod::{configuration::TlsSettings, listener::ListenAddress};
+
+use crate::helpers::spawn_app_with;
+
+#[tokio::test]
+async fn forms_larger_than_the_limit_are_rejected() {
+    let app = spawn_app_with(|c| c.application.max_form_payload_bytes = 64).await;
+    let name = "a".repeat(64);
+
+    let resp = app
+        .post_subscriptions(format!("name={}&email=ursula_le_guin%40gmail.com", name))
+        .await;
+
+    assert_eq!(resp.status().as_u16(), 413);
+}
+
+#[tokio::test]
+async fn https_is_served_when_a_certificate_is_configured() {
+    let app = spawn_app_with(|c| {
+        c.application.tls = Some(TlsSettings {
+            cert: "tests/fixtures/tls/cert.pem".into(),
+            key: "tests/fixtures/tls/key.pem".into(),
+        })
+    })
+    .await;
+    let cert = reqwest::Certificate::from_pem(include_bytes!("../fixtures/tls/cert.pem")).unwrap();
+    let client = reqwest::Client::builder()
+        .add_root_certificate(cert)
+        .build()
+        .unwrap();
+
+    assert!(app.address.starts_with("https://"));
+    let resp = client
+        .get(format!("{}/health/live", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert!(resp.status().is_success());
+}
+
+#[tokio::test]
+async fn requests_are_served_on_a_unix_socket_when_configured() {
+    let path = std::env::temp_dir().join(format!("{}.sock", Uuid::new_v4()));
+    let app = spawn_app_with(|c| c.application.unix_socket = Some(path.clone())).await;
+    assert_eq!(app.listen_address, ListenAddress::Unix(path.clone()));
+
+    let response = tokio::task::spawn_blocking(move || {
+        let mut stream = UnixStream::connect(&path).expect("Failed to connect to the socket");
+        stream
+            .write_all(b"GET /health/live HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        response
+    })
+    .await
+    .unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+}
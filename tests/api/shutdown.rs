@@ -0,0 +1,108 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use wiremock::{matchers::method, Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, spawn_app_with};
+
+#[tokio::test]
+async fn in_flight_requests_complete_during_shutdown() {
+    let app = spawn_app_with(|c| c.health.check_email_api = true).await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let address = app.address.clone();
+    let in_flight = tokio::spawn(async move {
+        reqwest::Client::new()
+            .get(format!("{}/health/ready", address))
+            .send()
+            .await
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    app.shutdown.trigger();
+
+    let res = in_flight
+        .await
+        .unwrap()
+        .expect("In-flight request was dropped");
+    assert_eq!(res.status().as_u16(), 200);
+    app.server.await.unwrap().expect("Server failed to stop");
+
+    assert!(reqwest::get(format!("{}/health", app.address))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn streamed_exports_complete_during_shutdown() {
+    let app = spawn_app().await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    // Holds the export back after its headers are sent
+    let mut lock = app.db_pool.begin().await.unwrap();
+    sqlx::query("LOCK TABLE subscriptions IN ACCESS EXCLUSIVE MODE")
+        .execute(&mut *lock)
+        .await
+        .unwrap();
+
+    let resp = app.export_subscribers("format=csv").await;
+    assert_eq!(resp.status().as_u16(), 200);
+    app.shutdown.trigger();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(
+        !app.server.is_finished(),
+        "Shutdown did not wait for the export"
+    );
+    lock.commit().await.unwrap();
+
+    let body = resp.text().await.expect("Export was cut short");
+    assert_eq!(body.lines().count(), 2);
+    app.server.await.unwrap().expect("Server failed to stop");
+}
+
+#[tokio::test]
+async fn shutdown_waits_for_workers_to_finish_their_unit_of_work() {
+    let app = spawn_app().await;
+    let finished = Arc::new(AtomicBool::new(false));
+
+    let shutdown = app.shutdown.clone();
+    let worker_finished = finished.clone();
+    app.shutdown.spawn_worker(async move {
+        while !shutdown.is_triggered() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        worker_finished.store(true, Ordering::SeqCst);
+    });
+
+    app.shutdown.trigger();
+    app.server.await.unwrap().expect("Server failed to stop");
+
+    assert!(finished.load(Ordering::SeqCst));
+    assert!(
+        app.db_pool.acquire().await.is_ok(),
+        "Test pool is not the app's"
+    );
+}
+
+#[tokio::test]
+async fn shutdown_gives_up_on_workers_after_the_deadline() {
+    let app = spawn_app_with(|c| c.application.shutdown_deadline_secs = 1).await;
+    app.shutdown.spawn_worker(std::future::pending());
+
+    app.shutdown.trigger();
+
+    tokio::time::timeout(Duration::from_secs(5), app.server)
+        .await
+        .expect("Shutdown did not respect its deadline")
+        .unwrap()
+        .expect("Server failed to stop");
+}
@@ -18,6 +18,7 @@ async fn subscriber_returns_200_for_valid_form_data() {
 
     let body = String::from("name=jakob&email=jaking@off.com");
     let resp = test_app.post_subscriptions(body).await;
+    test_app.dispatch_all_pending_emails().await;
 
     assert!(resp.status().is_success());
 }
@@ -35,6 +36,7 @@ async fn post_subscribe_persists_data_on_success() {
 
     let body = String::from("name=fastbyte%20bit&email=fast@byte.bit");
     test_app.post_subscriptions(body).await;
+    test_app.dispatch_all_pending_emails().await;
 
     let saved = sqlx::query!("SELECT name, email, status FROM subscriptions")
         .fetch_one(&test_app.db_pool)
@@ -105,8 +107,280 @@ async fn subscribe_sends_confirmation_mail_for_valid_data() {
         .await;
 
     app.post_subscriptions(body).await;
+    app.dispatch_all_pending_emails().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
     assert_eq!(confirmation_links.plain_text, confirmation_links.html);
 }
+
+#[tokio::test]
+async fn subscribe_without_lists_joins_the_default_list() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=jakob&email=jaking@off.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!(
+        r#"SELECT lists.slug, list_subscriptions.status FROM list_subscriptions
+        JOIN lists ON lists.id = list_subscriptions.list_id"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch list subscription");
+
+    assert_eq!(saved.slug, "newsletter");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribe_to_several_lists_persists_a_pending_subscription_per_list() {
+    let app = spawn_app().await;
+    app.create_list("daily").await.error_for_status().unwrap();
+    app.create_list("weekly").await.error_for_status().unwrap();
+
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=jakob&email=jaking@off.com&lists=daily,weekly".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let saved = sqlx::query!(
+        r#"SELECT lists.slug FROM list_subscriptions
+        JOIN lists ON lists.id = list_subscriptions.list_id
+        ORDER BY lists.slug"#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .expect("Failed to fetch list subscriptions");
+
+    let slugs: Vec<_> = saved.into_iter().map(|r| r.slug).collect();
+    assert_eq!(slugs, vec!["daily", "weekly"]);
+}
+
+#[tokio::test]
+async fn existing_subscribers_join_another_list_once_they_confirm_it() {
+    let app = spawn_app().await;
+    app.create_list("daily").await.error_for_status().unwrap();
+
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=jakob&email=jaking@off.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    app.post_subscriptions("name=someone%20else&email=jaking@off.com&lists=daily".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let list_statuses = || async {
+        sqlx::query!(
+            r#"SELECT lists.slug, list_subscriptions.status FROM list_subscriptions
+            JOIN lists ON lists.id = list_subscriptions.list_id
+            ORDER BY lists.slug"#
+        )
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.slug, r.status))
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        list_statuses().await,
+        vec![
+            ("daily".into(), "pending_confirmation".into()),
+            ("newsletter".into(), "confirmed".into()),
+        ]
+    );
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(
+        list_statuses().await,
+        vec![
+            ("daily".into(), "confirmed".into()),
+            ("newsletter".into(), "confirmed".into()),
+        ]
+    );
+    let saved = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        saved.name, "jakob",
+        "Signups must not change stored details"
+    );
+}
+
+#[tokio::test]
+async fn subscribe_returns_409_when_already_confirmed_on_every_requested_list() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=jakob&email=jaking@off.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let resp = app
+        .post_subscriptions("name=jakob&email=jaking@off.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(resp.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn subscribe_returns_400_for_unknown_or_invalid_lists() {
+    let app = spawn_app().await;
+
+    let scenarios = vec![
+        (
+            "name=test&email=test@mail.com&lists=missing",
+            "list does not exist",
+        ),
+        (
+            "name=test&email=test@mail.com&lists=Not%20A%20Slug",
+            "list is invalid",
+        ),
+        (
+            "name=test&email=test@mail.com&lists=newsletter,",
+            "list is empty",
+        ),
+    ];
+
+    for (payload, description) in scenarios {
+        let resp = app.post_subscriptions(payload.to_string()).await;
+        assert_eq!(
+            resp.status().as_u16(),
+            400,
+            "API did not return 400 BAD REQUEST when {}",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscribe_stores_hidden_attribute_fields() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(
+        "name=jakob&email=jaking@off.com&attr_country=DE&attr_source=landing-page&utm=ignored"
+            .into(),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let saved = sqlx::query!("SELECT attributes FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+
+    assert_eq!(
+        saved.attributes,
+        serde_json::json!({ "country": "DE", "source": "landing-page" })
+    );
+}
+
+#[tokio::test]
+async fn subscribe_returns_400_for_invalid_attribute_fields() {
+    let app = spawn_app().await;
+
+    let resp = app
+        .post_subscriptions("name=jakob&email=jaking@off.com&attr_Country=DE".into())
+        .await;
+
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribe_propagates_the_incoming_trace_to_the_email_api() {
+    let test_app = spawn_app().await;
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    reqwest::Client::new()
+        .post(format!("{}/subscribe", test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header(
+            "traceparent",
+            format!("00-{}-00f067aa0ba902b7-01", trace_id),
+        )
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    test_app.dispatch_all_pending_emails().await;
+
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let traceparent = email_request
+        .headers
+        .get(&"traceparent".into())
+        .expect("The email API call carries no traceparent")
+        .as_str();
+    assert!(
+        traceparent.starts_with(&format!("00-{}-", trace_id)),
+        "{} does not continue the trace",
+        traceparent
+    );
+}
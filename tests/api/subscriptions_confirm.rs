@@ -3,7 +3,22 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn subscribe_and_get_confirmation_link(app: &TestApp) -> reqwest::Url {
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.get_confirmation_links(email_request).html
+}
 
 #[tokio::test]
 async fn confirmation_without_token_returns_400() {
@@ -28,10 +43,11 @@ async fn link_returned_by_subscribe_rets_200_if_called() {
         .await;
 
     app.post_subscriptions(body.to_string()).await;
+    app.dispatch_all_pending_emails().await;
 
     let email_sent = &app.email_server.received_requests().await.unwrap()[0];
 
-    let confirmation_link = app.get_confirmation_links(&email_sent).html;
+    let confirmation_link = app.get_confirmation_links(email_sent).html;
     let resp = reqwest::get(confirmation_link).await.unwrap();
     assert_eq!(resp.status().as_u16(), 200);
 }
@@ -49,9 +65,10 @@ async fn clicking_on_confirmation_link_confirms_subscription() {
         .await;
 
     app.post_subscriptions(body).await;
+    app.dispatch_all_pending_emails().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     reqwest::get(confirmation_links.html)
         .await
@@ -68,3 +85,179 @@ async fn clicking_on_confirmation_link_confirms_subscription() {
     assert_eq!(saved.email, "guido@ferrari.com");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn clicking_on_confirmation_link_confirms_every_requested_list() {
+    let app = spawn_app().await;
+    app.create_list("daily").await.error_for_status().unwrap();
+    app.create_list("weekly").await.error_for_status().unwrap();
+
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=guido&email=guido@ferrari.com&lists=daily,weekly".into())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let statuses = sqlx::query!("SELECT status FROM list_subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to read list subscriptions");
+
+    assert_eq!(statuses.len(), 2);
+    assert!(statuses.iter().all(|r| r.status == "confirmed"));
+}
+
+#[tokio::test]
+async fn confirming_renders_an_html_page() {
+    let app = spawn_app().await;
+    let confirmation_link = subscribe_and_get_confirmation_link(&app).await;
+
+    let resp = reqwest::get(confirmation_link).await.unwrap();
+
+    assert_eq!(resp.status().as_u16(), 200);
+    assert!(resp.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    assert!(resp
+        .text()
+        .await
+        .unwrap()
+        .contains("Subscription confirmed"));
+}
+
+#[tokio::test]
+async fn following_the_link_again_says_it_is_already_confirmed() {
+    let app = spawn_app().await;
+    let confirmation_link = subscribe_and_get_confirmation_link(&app).await;
+    reqwest::get(confirmation_link.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let resp = reqwest::get(confirmation_link).await.unwrap();
+
+    assert_eq!(resp.status().as_u16(), 200);
+    assert!(resp.text().await.unwrap().contains("Already confirmed"));
+}
+
+#[tokio::test]
+async fn unknown_tokens_render_an_invalid_link_page() {
+    let app = spawn_app().await;
+
+    let resp = reqwest::get(format!(
+        "{}/subscriptions/confirm?subscription_token=doesnotexist",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(resp.status().as_u16(), 401);
+    assert!(resp
+        .text()
+        .await
+        .unwrap()
+        .contains("Invalid confirmation link"));
+}
+
+#[tokio::test]
+async fn expired_tokens_do_not_confirm_the_subscription() {
+    let app = spawn_app().await;
+    let confirmation_link = subscribe_and_get_confirmation_link(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let resp = reqwest::get(confirmation_link).await.unwrap();
+
+    assert_eq!(resp.status().as_u16(), 410);
+    assert!(resp
+        .text()
+        .await
+        .unwrap()
+        .contains("Confirmation link expired"));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn outcomes_are_redirected_to_when_configured() {
+    let app = spawn_app_with(|c| {
+        c.application.confirmation.redirect_url =
+            Some(String::from("https://example.com/welcome?lang=en"));
+    })
+    .await;
+    let confirmation_link = subscribe_and_get_confirmation_link(&app).await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    let resp = client.get(confirmation_link).send().await.unwrap();
+
+    assert_eq!(resp.status().as_u16(), 303);
+    assert_eq!(
+        resp.headers()["location"],
+        "https://example.com/welcome?lang=en&status=confirmed"
+    );
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_sends_a_new_link_replacing_an_expired_one() {
+    let app = spawn_app().await;
+    let expired_link = subscribe_and_get_confirmation_link(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let resp = reqwest::get(expired_link.clone()).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 410);
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let new_link = app.get_confirmation_links(email_request).html;
+    assert_ne!(new_link, expired_link);
+    // The link sent first no longer leads anywhere
+    let resp = reqwest::get(expired_link).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 401);
+
+    let resp = reqwest::get(new_link).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    let saved = sqlx::query!(
+        r#"SELECT s.status, l.status AS list_status
+        FROM subscriptions s JOIN list_subscriptions l ON l.subscriber_id = s.id"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "confirmed");
+    assert_eq!(saved.list_status, "confirmed");
+}
@@ -0,0 +1,284 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn subscribe(app: &TestApp, body: &str) -> String {
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let preferences_link = app.get_preferences_links(&email_request).html;
+    preferences_link
+        .query_pairs()
+        .find(|(k, _)| k == "token")
+        .map(|(_, v)| v.into_owned())
+        .unwrap()
+}
+
+async fn mount_email_server(app: &TestApp) {
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn post_change_email(app: &TestApp, body: String) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions/email", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn email_change_link(app: &TestApp, email_request: &wiremock::Request) -> reqwest::Url {
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Messages"][0]["To"][0]["Email"], "ursula@le-guin.com");
+    let text = body["Messages"][0]["TextPart"].as_str().unwrap();
+    let link = linkify::LinkFinder::new()
+        .links(text)
+        .find(|l| l.as_str().contains("/subscriptions/email/confirm"))
+        .unwrap();
+    let mut link = reqwest::Url::parse(link.as_str()).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+#[tokio::test]
+async fn email_is_only_changed_once_the_new_address_is_confirmed() {
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+    let token = subscribe(&app, "name=le%20guin&email=ursula@earthsea.com").await;
+
+    let resp = post_change_email(&app, format!("token={token}&email=ursula@le-guin.com")).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula@earthsea.com");
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let link = email_change_link(&app, &email_request);
+    let resp = reqwest::get(link.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(resp.headers()["Content-Type"], "text/html; charset=utf-8");
+    assert!(resp.text().await.unwrap().contains("Email address changed"));
+
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula@le-guin.com");
+
+    let change =
+        sqlx::query!("SELECT old_value, new_value FROM preference_changes WHERE field = 'email'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(change.old_value, "ursula@earthsea.com");
+    assert_eq!(change.new_value, "ursula@le-guin.com");
+
+    // Links can only be used once
+    let resp = reqwest::get(link).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 401);
+    assert!(resp
+        .text()
+        .await
+        .unwrap()
+        .contains("Invalid confirmation link"));
+}
+
+#[tokio::test]
+async fn email_change_confirmation_is_queued_in_the_outbox() {
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+    let token = subscribe(&app, "name=le%20guin&email=ursula@earthsea.com").await;
+
+    post_change_email(&app, format!("token={token}&email=ursula@le-guin.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Only the signup confirmation went out so far
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
+    let queued = sqlx::query!("SELECT recipient FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.recipient, "ursula@le-guin.com");
+
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    email_change_link(&app, &email_request);
+}
+
+#[tokio::test]
+async fn email_change_tokens_cannot_confirm_a_subscription() {
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+    let token = subscribe(&app, "name=le%20guin&email=ursula@earthsea.com").await;
+    post_change_email(&app, format!("token={token}&email=ursula@le-guin.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let mut link = email_change_link(&app, &email_request);
+    link.set_path("/subscriptions/confirm");
+
+    let resp = reqwest::get(link).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn changing_to_an_address_already_subscribed_does_not_tell_it_is() {
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+    let token = subscribe(&app, "name=le%20guin&email=ursula@earthsea.com").await;
+    subscribe(&app, "name=pratchett&email=terry@discworld.com").await;
+
+    let resp = post_change_email(&app, format!("token={token}&email=terry@discworld.com")).await;
+
+    assert_eq!(resp.status().as_u16(), 200);
+    assert!(resp
+        .text()
+        .await
+        .unwrap()
+        .contains("We sent a confirmation link to your new email address."));
+    let saved = sqlx::query!("SELECT email FROM subscriptions WHERE name = 'le guin'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula@earthsea.com");
+}
+
+#[tokio::test]
+async fn confirming_after_the_address_was_taken_returns_409() {
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+    let token = subscribe(&app, "name=le%20guin&email=ursula@earthsea.com").await;
+    post_change_email(&app, format!("token={token}&email=ursula@le-guin.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let link = email_change_link(&app, &email_request);
+
+    subscribe(&app, "name=someone&email=ursula@le-guin.com").await;
+    let resp = reqwest::get(link).await.unwrap();
+
+    assert_eq!(resp.status().as_u16(), 409);
+    assert!(resp
+        .text()
+        .await
+        .unwrap()
+        .contains("Email address already subscribed"));
+    let saved = sqlx::query!("SELECT email FROM subscriptions WHERE name = 'le guin'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula@earthsea.com");
+}
+
+#[tokio::test]
+async fn invalid_email_change_requests_are_rejected() {
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+    let token = subscribe(&app, "name=le%20guin&email=ursula@earthsea.com").await;
+
+    let resp = post_change_email(&app, format!("token={token}&email=not-an-email")).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let resp = post_change_email(&app, format!("token={token}&email=ursula@earthsea.com")).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let resp = post_change_email(&app, "token=unknown&email=ursula@le-guin.com".into()).await;
+    assert_eq!(resp.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn expired_email_change_links_return_410() {
+    let app = spawn_app().await;
+    mount_email_server(&app).await;
+    let token = subscribe(&app, "name=le%20guin&email=ursula@earthsea.com").await;
+    post_change_email(&app, format!("token={token}&email=ursula@le-guin.com"))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let link = email_change_link(&app, &email_request);
+    sqlx::query!(
+        "UPDATE subscription_tokens SET created_at = now() - interval '8 days'
+        WHERE purpose = 'change_email'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let resp = reqwest::get(link).await.unwrap();
+
+    assert_eq!(resp.status().as_u16(), 410);
+    assert!(resp
+        .text()
+        .await
+        .unwrap()
+        .contains("Confirmation link expired"));
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula@earthsea.com");
+}
@@ -0,0 +1,250 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn subscribe(app: &TestApp, body: &str) -> reqwest::Url {
+    Mock::given(path("/send"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.get_preferences_links(email_request).html
+}
+
+async fn post_preferences(app: &TestApp, body: String) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions/preferences", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn token_of(preferences_link: &reqwest::Url) -> String {
+    preferences_link
+        .query_pairs()
+        .find(|(k, _)| k == "token")
+        .map(|(_, v)| v.into_owned())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn confirmation_email_links_to_the_preference_center() {
+    let app = spawn_app().await;
+    let preferences_link = subscribe(&app, "name=le%20guin&email=ursula@earthsea.com").await;
+
+    let resp = reqwest::get(preferences_link).await.unwrap();
+
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.headers()["Content-Type"], "text/html; charset=utf-8");
+    let page = resp.text().await.unwrap();
+    assert!(page.contains(r#"value="le guin""#));
+    assert!(page.contains(r#"value="newsletter" checked"#));
+}
+
+#[tokio::test]
+async fn preferences_with_an_unknown_token_are_rejected() {
+    let app = spawn_app().await;
+
+    let resp = reqwest::get(format!(
+        "{}/subscriptions/preferences?token=not-a-token",
+        app.address
+    ))
+    .await
+    .unwrap();
+    assert_eq!(resp.status().as_u16(), 401);
+
+    let resp = post_preferences(&app, "token=not-a-token&name=bob&format=html".into()).await;
+    assert_eq!(resp.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn preferences_are_updated_and_recorded() {
+    let app = spawn_app().await;
+    app.create_list("daily").await.error_for_status().unwrap();
+    let token = token_of(&subscribe(&app, "name=le%20guin&email=ursula@earthsea.com").await);
+
+    let resp = post_preferences(
+        &app,
+        format!("token={token}&name=ursula%20le%20guin&format=text&lists=daily"),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert!(resp
+        .text()
+        .await
+        .unwrap()
+        .contains("Your preferences have been saved."));
+
+    let saved = sqlx::query!("SELECT name, email_format FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch subscriber");
+    assert_eq!(saved.name, "ursula le guin");
+    assert_eq!(saved.email_format, "text");
+
+    let lists = sqlx::query!(
+        r#"SELECT lists.slug, list_subscriptions.status FROM list_subscriptions
+        JOIN lists ON lists.id = list_subscriptions.list_id
+        ORDER BY lists.slug"#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .expect("Failed to fetch list subscriptions");
+    let lists: Vec<_> = lists.into_iter().map(|r| (r.slug, r.status)).collect();
+    assert_eq!(
+        lists,
+        vec![
+            ("daily".to_string(), "pending_confirmation".to_string()),
+            ("newsletter".to_string(), "unsubscribed".to_string()),
+        ]
+    );
+
+    let changes =
+        sqlx::query!("SELECT field, old_value, new_value FROM preference_changes ORDER BY field")
+            .fetch_all(&app.db_pool)
+            .await
+            .expect("Failed to fetch preference changes");
+    let changes: Vec<_> = changes
+        .into_iter()
+        .map(|r| (r.field, r.old_value, r.new_value))
+        .collect();
+    assert_eq!(
+        changes,
+        vec![
+            ("email_format".into(), "html".into(), "text".into()),
+            ("lists".into(), "newsletter".into(), "daily".into()),
+            ("name".into(), "le guin".into(), "ursula le guin".into()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn topics_added_before_confirming_are_confirmed_with_the_signup() {
+    let app = spawn_app().await;
+    app.create_list("daily").await.error_for_status().unwrap();
+    let token = token_of(&subscribe(&app, "name=le%20guin&email=ursula@earthsea.com").await);
+
+    post_preferences(
+        &app,
+        format!("token={token}&name=le%20guin&format=html&lists=newsletter&lists=daily"),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    reqwest::get(app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let lists = sqlx::query!(
+        r#"SELECT lists.slug, list_subscriptions.status FROM list_subscriptions
+        JOIN lists ON lists.id = list_subscriptions.list_id
+        ORDER BY lists.slug"#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .expect("Failed to fetch list subscriptions");
+    let lists: Vec<_> = lists.into_iter().map(|r| (r.slug, r.status)).collect();
+    assert_eq!(
+        lists,
+        vec![
+            ("daily".to_string(), "confirmed".to_string()),
+            ("newsletter".to_string(), "confirmed".to_string()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn invalid_preferences_are_rejected() {
+    let app = spawn_app().await;
+    let token = token_of(&subscribe(&app, "name=le%20guin&email=ursula@earthsea.com").await);
+
+    let scenarios = vec![
+        (
+            format!("token={token}&name=%20&format=html"),
+            "name is empty",
+        ),
+        (
+            format!("token={token}&name=%3Cb%3E&format=html"),
+            "name is invalid",
+        ),
+        (
+            format!("token={token}&name=ursula&format=pdf"),
+            "format is invalid",
+        ),
+        (
+            format!("token={token}&name=ursula&format=html&lists=missing"),
+            "list does not exist",
+        ),
+        (format!("token={token}&name=ursula"), "format is missing"),
+    ];
+
+    for (body, description) in scenarios {
+        let resp = post_preferences(&app, body).await;
+        assert_eq!(
+            resp.status().as_u16(),
+            400,
+            "API did not return 400 BAD REQUEST when {}",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn backfilled_preferences_tokens_are_regenerated() {
+    let app = spawn_app().await;
+    let preferences_link = subscribe(&app, "name=le%20guin&email=ursula@earthsea.com").await;
+    let app_token = token_of(&preferences_link);
+    let backfilled_token = "0123456789abcdef0123456789abcdef";
+    sqlx::query("UPDATE subscriptions SET preferences_token = $1 WHERE email = $2")
+        .bind(backfilled_token)
+        .bind("ursula@earthsea.com")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    sqlx::query(include_str!(
+        "../../migrations/20240505090314_regenerate_backfilled_preferences_tokens.sql"
+    ))
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let token: String = sqlx::query_scalar("SELECT preferences_token FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(token, backfilled_token);
+    assert_ne!(token, app_token);
+}
+
+#[tokio::test]
+async fn preferences_tokens_generated_by_the_app_are_kept() {
+    let app = spawn_app().await;
+    let preferences_link = subscribe(&app, "name=le%20guin&email=ursula@earthsea.com").await;
+
+    sqlx::query(include_str!(
+        "../../migrations/20240505090314_regenerate_backfilled_preferences_tokens.sql"
+    ))
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let resp = reqwest::get(preferences_link).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+}